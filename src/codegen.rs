@@ -1,8 +1,13 @@
+use std::io::{self, BufRead, Write};
+
 use super::table::{RelAddr, NameTable};
+use super::error::{CompileError, VmError};
+use super::get_source::Span;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OpCode {                                 // 命令語のコード
     Lit, Opr, Lod, Sto, Cal, Ret, Ict, Jmp, Jpc,
+    Lda, Ldx, Stx, Idx, Ldi, Sti, Wrs,
 }
 
 // impl OpCode {
@@ -17,14 +22,21 @@ pub enum OpCode {                                 // 命令語のコード
 //             OpCode::Ict => "ict",
 //             OpCode::Jmp => "jmp",
 //             OpCode::Jpc => "jpc",
+//             OpCode::Lda => "lda",
+//             OpCode::Ldx => "ldx",
+//             OpCode::Stx => "stx",
+//             OpCode::Idx => "idx",
+//             OpCode::Ldi => "ldi",
+//             OpCode::Sti => "sti",
+//             OpCode::Wrs => "wrs",
 //         }
 //     }
 // }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Operator {                               // 演算命令のコード
-    Neg, Add, Sub, Mul, Div, Odd, Eq, Ls, Gr,
-    Neq, Lseq, Greq, Wrt, Wrl,
+    Neg, Add, Sub, Mul, Div, Mod, Odd, Eq, Ls, Gr,
+    Neq, Lseq, Greq, Wrt, Wrf, Wrl, Rd,
 }
 
 // impl Operator {
@@ -35,6 +47,7 @@ pub enum Operator {                               // 演算命令のコード
 //             Operator::Sub  => "sub",
 //             Operator::Mul  => "mul",
 //             Operator::Div  => "div",
+//             Operator::Mod  => "mod",
 //             Operator::Odd  => "odd",
 //             Operator::Eq   => "eq",
 //             Operator::Ls   => "ls",
@@ -43,82 +56,124 @@ pub enum Operator {                               // 演算命令のコード
 //             Operator::Lseq => "lseq",
 //             Operator::Greq => "greq",
 //             Operator::Wrt  => "wrt",
+//             Operator::Wrf  => "wrf",
 //             Operator::Wrl  => "wrl",
+//             Operator::Rd   => "rd",
 //         }
 //     }
 // }
 
-const MAXCODE: usize = 100;     // 目的コードの最大長さ
 const MAXMEM: usize = 2000;     // 実行時スタックの最大長さ
 const MAXREG: usize = 20;       // 演算レジスタスタックの最大長さ
-const MAXLEVEL: usize = 5;      // ブロックの最大深さ
 
 #[derive(Copy, Clone, Debug)]
 pub enum InstU {
     RelAddr(RelAddr),
+    Ret { raddr: RelAddr, value: bool },  // ret命令：ブロックのレベルとパラメータ数、値を返すかどうか
     Value(i32),
     Operator(Operator),
+    Str { index: i32, width: i32 },       // wrs命令：文字列定数の表のインデックスと桁数
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct Inst {               // 命令語の型
     op_code: OpCode,
     u: InstU,
+    span: Span,                 // この命令語を生成したソースプログラム上の位置
+}
+
+impl Inst {
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 pub struct CodeGenerator<'a> {
     code: Vec<Inst>,                  // 目的コードが入る
+    strings: Vec<String>,             // 文字列定数の表（wrs命令のアドレス部はこの表のインデックス）
     c_index: i32,                     // 最後に生成した命令語のインデックス
+    span: Span,                       // これから生成する命令語のソースプログラム上の位置
+    max_code: Option<usize>,          // 目的コードの最大長さ（Noneなら制限なし）
     pub table: &'a mut NameTable,
 }
 
 impl<'a> CodeGenerator<'a> {
     pub fn new(table: &'a mut NameTable) -> CodeGenerator<'a> {
-        CodeGenerator { code: Vec::new(), c_index: -1, table }
+        CodeGenerator { code: Vec::new(), strings: Vec::new(), c_index: -1, span: Span::default(), max_code: None, table }
+    }
+    pub fn set_max_code(&mut self, max_code: Option<usize>) {         // 目的コードの最大長さをセット
+        self.max_code = max_code;
+    }
+    pub fn set_span(&mut self, span: Span) {                          // これから生成する命令語の位置をセット
+        self.span = span;
+    }
+    pub fn code(&self) -> &[Inst] {                                   // 生成した目的コードを返す
+        &self.code
+    }
+    pub fn strings(&self) -> &[String] {                              // 文字列定数の表を返す
+        &self.strings
+    }
+    pub fn enter_string(&mut self, s: String) -> i32 {                // 文字列定数を表に登録して、そのインデックスを返す（同じ文字列は共有）
+        if let Some(i) = self.strings.iter().position(|t| *t == s) {
+            return i as i32;
+        }
+        self.strings.push(s);
+        (self.strings.len() - 1) as i32
     }
     pub fn next_code(&self) -> i32 {                                  // 次の命令語のアドレスを返す
         self.c_index + 1
     }
-    fn check_max(&mut self) {                                         // 目的コードのインデックスの増加とチェック
-        self.c_index += 1;
-        if self.c_index < MAXCODE as i32 {
-            return;
-        } else {
-            println!("too many code");
-            std::process::exit(1);
+    fn check_max(&mut self) -> Result<(), CompileError> {             // 目的コードのインデックスの増加とチェック
+        if let Some(limit) = self.max_code {
+            if self.code.len() >= limit {
+                return Err(CompileError::TooManyCode { limit });
+            }
         }
+        self.c_index += 1;
+        Ok(())
     }
-    pub fn gen_code_v(&mut self, op: OpCode, v: i32) -> i32 {         // 命令語の生成、アドレス部にv
-        self.check_max();
-        self.code.push(Inst { op_code: op, u: InstU::Value(v) });
-        self.c_index
+    pub fn gen_code_v(&mut self, op: OpCode, v: i32) -> Result<i32, CompileError> {         // 命令語の生成、アドレス部にv
+        self.check_max()?;
+        self.code.push(Inst { op_code: op, u: InstU::Value(v), span: self.span });
+        Ok(self.c_index)
     }
-    pub fn gen_code_t(&mut self, op: OpCode, ti: i32) -> i32 {        // 命令語の生成、アドレスは名前表から
-        self.check_max();
-        self.code.push(Inst { op_code: op, u: InstU::RelAddr(self.table.rel_addr(ti)) });
-        self.c_index
+    pub fn gen_code_t(&mut self, op: OpCode, ti: i32) -> Result<i32, CompileError> {        // 命令語の生成、アドレスは名前表から
+        self.check_max()?;
+        self.code.push(Inst { op_code: op, u: InstU::RelAddr(self.table.rel_addr(ti)), span: self.span });
+        Ok(self.c_index)
     }
-    pub fn gen_code_o(&mut self, p: Operator) -> i32 {                // 命令語の生成、アドレス部に演算命令
-        self.check_max();
-        self.code.push(Inst { op_code: OpCode::Opr, u: InstU::Operator(p) });
-        self.c_index
+    pub fn gen_code_o(&mut self, p: Operator) -> Result<i32, CompileError> {                // 命令語の生成、アドレス部に演算命令
+        self.check_max()?;
+        self.code.push(Inst { op_code: OpCode::Opr, u: InstU::Operator(p), span: self.span });
+        Ok(self.c_index)
     }
-    pub fn gen_code_r(&mut self) -> i32 {                             // ret命令語の生成
-        if self.code[self.c_index as usize].op_code == OpCode::Ret {  // 直前がretなら生成せず
-            return self.c_index;
-        }
-        self.check_max();
-        self.code.push(Inst { op_code: OpCode::Ret, u: InstU::RelAddr(
-            RelAddr {
+    pub fn gen_code_s(&mut self, index: i32, width: i32) -> Result<i32, CompileError> {     // wrs命令語の生成、文字列とその桁数
+        self.check_max()?;
+        self.code.push(Inst { op_code: OpCode::Wrs, u: InstU::Str { index, width }, span: self.span });
+        Ok(self.c_index)
+    }
+    pub fn gen_code_r(&mut self) -> Result<i32, CompileError> {                             // ret命令語の生成
+        self.check_max()?;                                            // 直前がretでも生成する（if文などの飛び先になりうる）
+        self.code.push(Inst { op_code: OpCode::Ret, u: InstU::Ret {
+            raddr: RelAddr {
                 level: self.table.block_level(),
                 addr: self.table.func_pars().unwrap()                 // パラメータ数（実行スタックの解放用）
-            }
-        )});
-        self.c_index
+            },
+            value: self.table.returns_value(),                        // 手続きと主ブロックは値を返さない
+        }, span: self.span });
+        Ok(self.c_index)
     }
     pub fn back_patch(&mut self, i: usize) {                          // 命令語のバックパッチ（次の番地を）
         self.code[i].u = InstU::Value(self.c_index + 1);
     }
+    pub fn patch_value(&mut self, i: usize, v: i32) {                 // 命令語のアドレス部をあとからvに変更
+        self.code[i].u = InstU::Value(v);
+    }
+    pub fn patch_addr(&mut self, i: usize, addr: i32) {               // cal命令の飛び先をあとでaddrに変更（レベルはそのまま）
+        if let InstU::RelAddr(r) = self.code[i].u {
+            self.code[i].u = InstU::RelAddr(RelAddr { addr, ..r });
+        }
+    }
     // pub fn print_code(&self, i: usize) {   // 命令語の印字
     //     let op_code = self.code[i].op_code;
     //     match self.code[i].u {
@@ -144,48 +199,114 @@ impl<'a> CodeGenerator<'a> {
     // }
     pub fn print_code(&self) {            // 目的コード（命令語）のリスティング
         for c in self.code.iter() {
-            println!("{:?}", c);
+            println!("Inst {{ op_code: {:?}, u: {:?} }}  ({}:{})", c.op_code, c.u, c.span.line, c.span.column);
+        }
+        for (i, s) in self.strings.iter().enumerate() {
+            println!("String {}: {:?}", i, s);
         }
     }
-    pub fn execute(&self) {               // 目的コード（命令語）の実行
-        let mut stack: [i32; MAXMEM] = [0; MAXMEM];         // 実行時スタック
-        let mut display: [i32; MAXLEVEL] = [0; MAXLEVEL];   // 現在見える各ブロックの先頭番地のディスプレイ
-
-        let mut pc: usize = 0;             // pc: 命令語のカウンタ
-        let mut top: usize = 0;            // top: 次にスタックに入れる場所
-
-        stack[0] = 0; stack[1] = 0;
+    pub fn execute(&self) -> Result<(), VmError> { // 目的コード（命令語）を標準入出力で実行
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.execute_with(&mut stdin.lock(), &mut stdout.lock())
+    }
+    pub fn execute_with<R: BufRead, W: Write>(&self, input: &mut R, output: &mut W) -> Result<(), VmError> {
+        // 目的コード（命令語）の実行。入力はinputから読み、出力はoutputに書く
+        let mut m = Machine::new();
+        let result = self.run(&mut m, input, output);
+        output.flush()?;
+        result
+    }
+    fn run<R: BufRead, W: Write>(&self, m: &mut Machine, input: &mut R, output: &mut W) -> Result<(), VmError> {
         // stack[top] は callee で壊すディスプレイの退避場所
         // stack[top+1] は caller への戻り番地
-        display[0] = 0;
         // 主ブロックの先頭番地は 0
-
         while {
-            let i = self.code[pc];         // これから実行する命令語
-            pc += 1;
+            if m.pc >= self.code.len() {   // 範囲外への飛び越し
+                return Err(VmError::InvalidJump { pc: m.cur, depth: m.depth });
+            }
+            let i = self.code[m.pc];       // これから実行する命令語
+            m.cur = m.pc;
+            m.pc += 1;
             match i.op_code {
                 OpCode::Lit => {
-                    stack[top] = match i.u {
+                    let v = match i.u {
                         InstU::Value(v) => v,
                         _ => unreachable!(),
                     };
-                    top += 1;
+                    m.push(v)?;
                 },
                 OpCode::Lod => {
                     let index = match i.u {
-                        InstU::RelAddr(r) => { (display[r.level as usize] + r.addr) as usize },
+                        InstU::RelAddr(r) => m.address(r)?,
                         _ => unreachable!(),
                     };
-                    stack[top] = stack[index];
-                    top += 1;
+                    let v = m.stack[index];
+                    m.push(v)?;
                 },
                 OpCode::Sto => {
                     let index = match i.u {
-                        InstU::RelAddr(r) => { (display[r.level as usize] + r.addr) as usize },
+                        InstU::RelAddr(r) => m.address(r)?,
+                        _ => unreachable!(),
+                    };
+                    m.stack[index] = m.pop()?;
+                },
+                OpCode::Lda => {                       // 変数の番地そのものを積む（配列の先頭番地）
+                    let index = match i.u {
+                        InstU::RelAddr(r) => m.address(r)?,
+                        _ => unreachable!(),
+                    };
+                    m.push(index as i32)?;
+                },
+                OpCode::Ldx => {                       // 配列の先頭番地と添字を降ろして、その要素を積む
+                    let size = match i.u {
+                        InstU::Value(v) => v,
+                        _ => unreachable!(),
+                    };
+                    let index = m.element(size)?;
+                    let v = m.stack[index];
+                    m.push(v)?;
+                },
+                OpCode::Stx => {                       // 値と配列の先頭番地と添字を降ろして、その要素に入れる
+                    let size = match i.u {
+                        InstU::Value(v) => v,
+                        _ => unreachable!(),
+                    };
+                    let v = m.pop()?;
+                    let index = m.element(size)?;
+                    m.stack[index] = v;
+                },
+                OpCode::Idx => {                       // 配列の先頭番地と添字を降ろして、その要素の番地を積む
+                    let size = match i.u {
+                        InstU::Value(v) => v,
+                        _ => unreachable!(),
+                    };
+                    let index = m.element(size)?;
+                    m.push(index as i32)?;
+                },
+                OpCode::Ldi => {                       // varパラメータが指す変数の値を積む
+                    let index = match i.u {
+                        InstU::RelAddr(r) => m.address(r)?,
+                        _ => unreachable!(),
+                    };
+                    let index = m.indirect(m.stack[index])?;
+                    let v = m.stack[index];
+                    m.push(v)?;
+                },
+                OpCode::Sti => {                       // varパラメータが指す変数に値を入れる
+                    let index = match i.u {
+                        InstU::RelAddr(r) => m.address(r)?,
+                        _ => unreachable!(),
+                    };
+                    let index = m.indirect(m.stack[index])?;
+                    m.stack[index] = m.pop()?;
+                },
+                OpCode::Wrs => {                       // 文字列定数の表のindex番目を桁数の幅に右寄せで出力する
+                    let (index, width) = match i.u {
+                        InstU::Str { index, width } => (index as usize, width),
                         _ => unreachable!(),
                     };
-                    top -= 1;
-                    stack[index] = stack[top];
+                    write_padded(output, &self.strings[index], width)?;
                 },
                 OpCode::Cal => {
                     // r.level は callee の名前のレベル
@@ -195,34 +316,48 @@ impl<'a> CodeGenerator<'a> {
                         _ => unreachable!(),
                     };
                     let lev = level + 1;
-                    stack[top] = display[lev];   // display[lev] の退避
-                    stack[top + 1] = pc as i32;
-                    display[lev] = top as i32;   // 現在の top が callee のブロックの先頭番地
-                    pc = addr;
+                    if m.display.len() <= lev {
+                        m.display.resize(lev + 1, 0);
+                    }
+                    if m.top + 1 >= MAXMEM {
+                        return Err(m.stack_overflow());
+                    }
+                    m.stack[m.top] = m.display[lev];    // display[lev] の退避
+                    m.stack[m.top + 1] = m.pc as i32;
+                    m.display[lev] = m.top as i32;      // 現在の top が callee のブロックの先頭番地
+                    m.pc = addr;
+                    m.depth += 1;
                 },
                 OpCode::Ret => {
-                    let (level, addr) = match i.u {
-                        InstU::RelAddr(r) => (r.level as usize, r.addr as usize),
+                    let (level, addr, value) = match i.u {
+                        InstU::Ret { raddr: r, value } => (r.level as usize, r.addr as usize, value),
                         _ => unreachable!(),
                     };
-                    top -= 1;
-                    let temp = stack[top];            // スタックのトップにあるものが返す値
-                    top = display[level] as usize;    // top を呼ばれたときの値に戻す
-                    display[level] = stack[top];      // 壊したディスプレイの回復
-                    pc = stack[top + 1] as usize;
-                    top -= addr;                      // 実引数の分だけトップを戻す
-                    stack[top] = temp;                // 返す値をスタックのトップへ
-                    top += 1;
+                    let temp = if value {               // スタックのトップにあるものが返す値
+                        Some(m.pop()?)
+                    } else {
+                        None
+                    };
+                    m.top = m.display[level] as usize;  // top を呼ばれたときの値に戻す
+                    m.display[level] = m.stack[m.top];  // 壊したディスプレイの回復
+                    m.pc = m.stack[m.top + 1] as usize;
+                    if m.top < addr {
+                        return Err(m.stack_underflow());
+                    }
+                    m.top -= addr;                      // 実引数の分だけトップを戻す
+                    if let Some(temp) = temp {
+                        m.push(temp)?;                  // 返す値をスタックのトップへ
+                    }
+                    m.depth = m.depth.saturating_sub(1);
                 },
                 OpCode::Ict => {
                     let v = match i.u {
                         InstU::Value(v) => v as usize,
                         _ => unreachable!(),
                     };
-                    top += v;
-                    if top >= MAXMEM - MAXREG {
-                        println!("stack overflow");
-                        std::process::exit(1);
+                    m.top += v;
+                    if m.top >= MAXMEM - MAXREG {
+                        return Err(m.stack_overflow());
                     }
                 },
                 OpCode::Jmp => {
@@ -230,16 +365,15 @@ impl<'a> CodeGenerator<'a> {
                         InstU::Value(v) => v as usize,
                         _ => unreachable!(),
                     };
-                    pc = v;
+                    m.pc = v;
                 },
                 OpCode::Jpc => {
-                    top -= 1;
-                    if stack[top] == 0 {
+                    if m.pop()? == 0 {
                         let v = match i.u {
                             InstU::Value(v) => v as usize,
                             _ => unreachable!(),
                         };
-                        pc = v;
+                        m.pc = v;
                     }
                 },
                 OpCode::Opr => {
@@ -249,64 +383,184 @@ impl<'a> CodeGenerator<'a> {
                     };
                     match optr {
                         Operator::Neg => {
-                            stack[top-1] = -stack[top-1];
+                            let a = m.pop()?;
+                            let v = a.checked_neg().ok_or_else(|| m.overflow())?;
+                            m.push(v)?;
                         },
                         Operator::Add => {
-                            top -= 1;
-                            stack[top-1] += stack[top];
+                            let (a, b) = m.pop2()?;
+                            let v = a.checked_add(b).ok_or_else(|| m.overflow())?;
+                            m.push(v)?;
                         },
                         Operator::Sub => {
-                            top -= 1;
-                            stack[top-1] -= stack[top];
+                            let (a, b) = m.pop2()?;
+                            let v = a.checked_sub(b).ok_or_else(|| m.overflow())?;
+                            m.push(v)?;
                         },
                         Operator::Mul => {
-                            top -= 1;
-                            stack[top-1] *= stack[top];
+                            let (a, b) = m.pop2()?;
+                            let v = a.checked_mul(b).ok_or_else(|| m.overflow())?;
+                            m.push(v)?;
                         },
                         Operator::Div => {
-                            top -= 1;
-                            stack[top-1] /= stack[top];
+                            let (a, b) = m.pop2()?;
+                            if b == 0 {
+                                return Err(VmError::DivisionByZero { pc: m.cur, depth: m.depth });
+                            }
+                            let v = a.checked_div(b).ok_or_else(|| m.overflow())?;
+                            m.push(v)?;
+                        },
+                        Operator::Mod => {             // 剰余は被除数と同じ符号（商は0の方向に切り捨て）
+                            let (a, b) = m.pop2()?;
+                            if b == 0 {
+                                return Err(VmError::DivisionByZero { pc: m.cur, depth: m.depth });
+                            }
+                            m.push(a.wrapping_rem(b))?;   // i32::MIN mod -1 は 0
                         },
                         Operator::Odd => {
-                            stack[top-1] = stack[top-1] & 1;
+                            let a = m.pop()?;
+                            m.push(a & 1)?;
                         },
                         Operator::Eq => {
-                            top -= 1;
-                            stack[top-1] = (stack[top-1] == stack[top]) as i32;
+                            let (a, b) = m.pop2()?;
+                            m.push((a == b) as i32)?;
                         },
                         Operator::Ls => {
-                            top -= 1;
-                            stack[top-1] = (stack[top-1] < stack[top]) as i32;
+                            let (a, b) = m.pop2()?;
+                            m.push((a < b) as i32)?;
                         },
                         Operator::Gr => {
-                            top -= 1;
-                            stack[top-1] = (stack[top-1] > stack[top]) as i32;
+                            let (a, b) = m.pop2()?;
+                            m.push((a > b) as i32)?;
                         },
                         Operator::Neq => {
-                            top -= 1;
-                            stack[top-1] = (stack[top-1] != stack[top]) as i32;
+                            let (a, b) = m.pop2()?;
+                            m.push((a != b) as i32)?;
                         },
                         Operator::Lseq => {
-                            top -= 1;
-                            stack[top-1] = (stack[top-1] <= stack[top]) as i32;
+                            let (a, b) = m.pop2()?;
+                            m.push((a <= b) as i32)?;
                         },
                         Operator::Greq => {
-                            top -= 1;
-                            stack[top-1] = (stack[top-1] >= stack[top]) as i32;
+                            let (a, b) = m.pop2()?;
+                            m.push((a >= b) as i32)?;
                         },
                         Operator::Wrt => {
-                            use std::io::Write;
-                            top -= 1;
-                            print!("{}", stack[top]);
-                            std::io::stdout().flush().unwrap();
+                            let a = m.pop()?;
+                            write!(output, "{}", a)?;
+                        },
+                        Operator::Wrf => {             // 値を桁数の幅に右寄せで出力
+                            let (a, width) = m.pop2()?;
+                            write_padded(output, &a.to_string(), width)?;
                         },
                         Operator::Wrl => {
-                            println!("");
+                            writeln!(output)?;
+                        },
+                        Operator::Rd => {
+                            output.flush()?;          // 入力を促す出力を先に出しておく
+                            let v = m.read_int(input)?;
+                            m.push(v)?;
                         }
                     }
                 },
             }
-            pc != 0
+            m.pc != 0
         } {}
+        Ok(())
+    }
+}
+
+fn write_padded<W: Write>(output: &mut W, text: &str, width: i32) -> io::Result<()> {
+    // textを幅widthに右寄せで出力（足りない分を空白で詰める）
+    let pad = (width.max(0) as usize).saturating_sub(text.chars().count());
+    for _ in 0..pad {
+        write!(output, " ")?;
+    }
+    write!(output, "{}", text)
+}
+
+struct Machine {                           // 仮想機械の状態
+    stack: [i32; MAXMEM],                  // 実行時スタック
+    display: Vec<i32>,                     // 現在見える各ブロックの先頭番地のディスプレイ（必要に応じて伸ばす）
+    pc: usize,                             // pc: 命令語のカウンタ
+    cur: usize,                            // 実行中の命令語のアドレス（エラーの報告用）
+    top: usize,                            // top: 次にスタックに入れる場所
+    depth: usize,                          // 関数呼び出しの深さ
+    words: Vec<String>,                    // 入力の行のうち、まだ読んでいない語（逆順）
+}
+
+impl Machine {
+    fn new() -> Machine {
+        Machine { stack: [0; MAXMEM], display: vec![0], pc: 0, cur: 0, top: 0, depth: 0, words: Vec::new() }
+    }
+    fn stack_overflow(&self) -> VmError {
+        VmError::StackOverflow { pc: self.cur, depth: self.depth }
+    }
+    fn stack_underflow(&self) -> VmError {
+        VmError::StackUnderflow { pc: self.cur, depth: self.depth }
+    }
+    fn overflow(&self) -> VmError {
+        VmError::ArithmeticOverflow { pc: self.cur, depth: self.depth }
+    }
+    fn invalid_address(&self) -> VmError {
+        VmError::InvalidAddress { pc: self.cur, depth: self.depth }
+    }
+    fn push(&mut self, v: i32) -> Result<(), VmError> {     // スタックに積む
+        if self.top >= MAXMEM {
+            return Err(self.stack_overflow());
+        }
+        self.stack[self.top] = v;
+        self.top += 1;
+        Ok(())
+    }
+    fn pop(&mut self) -> Result<i32, VmError> {             // スタックから降ろす
+        if self.top == 0 {
+            return Err(self.stack_underflow());
+        }
+        self.top -= 1;
+        Ok(self.stack[self.top])
+    }
+    fn pop2(&mut self) -> Result<(i32, i32), VmError> {     // 二項演算の左右の値を降ろす
+        let b = self.pop()?;
+        let a = self.pop()?;
+        Ok((a, b))
+    }
+    fn read_int<R: BufRead>(&mut self, input: &mut R) -> Result<i32, VmError> {  // 空白で区切られた整数を１つ読む
+        while self.words.is_empty() {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Err(VmError::UnexpectedEof { pc: self.cur, depth: self.depth });
+            }
+            self.words = line.split_whitespace().rev().map(String::from).collect();
+        }
+        let word = self.words.pop().unwrap();
+        word.parse().map_err(|_| VmError::InvalidInput { pc: self.cur, depth: self.depth })
+    }
+    fn element(&mut self, size: i32) -> Result<usize, VmError> {  // 先頭番地と添字を降ろして配列の要素の番地を返す
+        let (base, i) = self.pop2()?;
+        if i < 0 || i >= size {
+            return Err(VmError::IndexOutOfBounds { index: i, size, pc: self.cur, depth: self.depth });
+        }
+        if base < 0 {                              // 先頭番地が壊れているときは足す前に止める
+            return Err(self.invalid_address());
+        }
+        let addr = base.checked_add(i).ok_or_else(|| self.invalid_address())?;
+        self.indirect(addr)
+    }
+    fn indirect(&self, addr: i32) -> Result<usize, VmError> {  // 番地として使う値の検査
+        if addr < 0 || addr as usize >= MAXMEM {
+            return Err(self.invalid_address());
+        }
+        Ok(addr as usize)
+    }
+    fn address(&self, r: RelAddr) -> Result<usize, VmError> {  // 変数・パラメータの実行時スタック上の番地
+        let index = self.display[r.level as usize] + r.addr;
+        if index < 0 {
+            return Err(self.stack_underflow());
+        }
+        if index as usize >= MAXMEM {
+            return Err(self.stack_overflow());
+        }
+        Ok(index as usize)
     }
 }
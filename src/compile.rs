@@ -1,104 +1,243 @@
-use super::{get_source::*, table::*, codegen::*};
+use super::{get_source::*, table::*, codegen::*, diagnostic::*, error::*};
 
-const MIN_ERROR: i32 = 3;     // エラーがこれ以下なら実行
+const MIN_ERROR: usize = 3;   // エラーがこれ以下なら実行
 const FIRST_ADDR: i32 = 2;    // 各ブロックの最初の変数のアドレス
+const MAX_WIDTH: i32 = 1000; // 出力の桁数の最大値
+const UNRESOLVED: i32 = -1;   // 定義のない前方宣言の先頭番地（実行すると範囲外への飛び越しになる）
+
+#[derive(Default)]
+struct LoopPatches {          // 繰り返しの中のbreak・continueのjmp命令（あとでバックパッチ）
+    breaks: Vec<i32>,         // 繰り返しの出口へ飛ぶjmp命令
+    continues: Vec<i32>,      // 次の繰り返しへ飛ぶjmp命令
+}
+
+fn is_variable(k: KindT) -> bool {  // 値を代入できる名前の種類か？
+    matches!(k, KindT::VarId | KindT::ParId | KindT::VarParId)
+}
+
+pub fn is_executable(diagnostics: &[Diagnostic]) -> bool {  // エラーの個数が少なく、実行してよいかどうかの判定
+    error_count(diagnostics) < MIN_ERROR
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CompilerOptions {           // コンパイラの設定
+    pub max_code: Option<usize>,       // 目的コードの最大長さ（Noneなら制限なし）
+    pub max_names: Option<usize>,      // 名前表の最大長さ（Noneなら制限なし）
+    pub max_level: Option<usize>,      // ブロックの最大深さ（Noneなら制限なし）
+}
 
 pub struct Compiler<'a, 'b, 'c, 'd> {
     token: Token,                      // 次のトークンを入れておく
     lex: &'a mut Lexer<'c>,            // 字句解析のメソッドを使うための参照
     gen: &'b mut CodeGenerator<'d>,    // アセンブリ生成のメソッドを使うための参照
+    loop_vars: Vec<i32>,               // 実行中のfor文の制御変数のインデックス（代入できない）
+    loops: Vec<LoopPatches>,           // コンパイル中の繰り返し（内側ほどうしろ）
 }                                      // テーブルへの参照はgenが保持している
 
 impl<'a, 'b, 'c, 'd> Compiler<'a, 'b, 'c, 'd> {
     pub fn new(lex: &'a mut Lexer<'c>, gen: &'b mut CodeGenerator<'d>) -> Compiler<'a, 'b, 'c, 'd> {
+        Compiler::with_options(lex, gen, CompilerOptions::default())
+    }
+    pub fn with_options(lex: &'a mut Lexer<'c>, gen: &'b mut CodeGenerator<'d>, options: CompilerOptions)
+                        -> Compiler<'a, 'b, 'c, 'd> {
+        gen.set_max_code(options.max_code);
+        gen.table.set_max_names(options.max_names);
+        gen.table.set_max_level(options.max_level);
         Compiler {
-            token: Token { kind: KeyId::Nul, u: TokenContent::Nothing },  // 適当なトークンで初期化する
-            lex, gen
+            token: Token { kind: KeyId::Nul, u: TokenContent::Nothing, span: Span::default() },  // 適当なトークンで初期化する
+            lex, gen, loop_vars: Vec::new(), loops: Vec::new(),
         }
     }
-    pub fn compile(&mut self) -> bool {
-        println!("start compilation:\n");
-        self.token = self.lex.next_token();            // 最初のトークン
-        self.gen.table.block_begin(FIRST_ADDR);
-                                                       // これ以後の宣言は最初のブロックのもの
-        self.block(0);                                 // 0はダミー（主ブロックの関数名はない）
-        let i = self.lex.error_n();                    // エラーメッセージの個数
-        if i != 0 {
-            if i == 1 {
-                println!("1 error occur");
-            } else {
-                println!("{} errors occur", i);
-            }
+    pub fn compile(&mut self) -> Result<Vec<Diagnostic>, CompileError> {  // コンパイルして、診断を返す
+        if let Err(err) = self.program() {             // 続けられないエラーも診断に加えておく
+            self.lex.fatal(err);
+            return Err(err);
         }
-        i < MIN_ERROR                                  // エラーメッセージの個数が少ないかどうかの判定
+        Ok(self.lex.diagnostics().to_vec())
+    }
+    pub fn diagnostics(&self) -> &[Diagnostic] {       // これまでの診断を返す（コンパイルを中断した場合も）
+        self.lex.diagnostics()
+    }
+    fn program(&mut self) -> Result<(), CompileError> {
+        self.token = self.lex.next_token()?;           // 最初のトークン
+        self.gen.table.block_begin(FIRST_ADDR)?;
+                                                       // これ以後の宣言は最初のブロックのもの
+        self.block(0)                                  // 0はダミー（主ブロックの関数名はない）
     }
-    fn block(&mut self, p_index: i32) {                       // ブロックのコンパイル（p_indexはこのブロックの関数名のインデックス）
-        let back_p = self.gen.gen_code_v(OpCode::Jmp, 0);  // 内部関数を飛び越す命令、あとでバックパッチ
+    fn block(&mut self, p_index: i32) -> Result<(), CompileError> { // ブロックのコンパイル（p_indexはこのブロックの関数名のインデックス）
+        self.gen.set_span(self.token.span);
+        let back_p = self.gen.gen_code_v(OpCode::Jmp, 0)?; // 内部関数を飛び越す命令、あとでバックパッチ
 
         loop {                                             // 宣言部のコンパイルを繰り返す
             match self.token.kind {
                 KeyId::Const => {                          // 定数宣言部のコンパイル
-                    self.token = self.lex.next_token();
-                    self.const_decl();
+                    self.token = self.lex.next_token()?;
+                    self.const_decl()?;
                 },
                 KeyId::Var => {                            // 変数宣言部のコンパイル
-                    self.token = self.lex.next_token();
-                    self.var_decl();
+                    self.token = self.lex.next_token()?;
+                    self.var_decl()?;
                 },
                 KeyId::Func => {                           // 関数宣言部のコンパイル
-                    self.token = self.lex.next_token();
-                    self.func_decl();
+                    self.token = self.lex.next_token()?;
+                    self.func_decl(KindT::FuncId)?;
+                },
+                KeyId::Proc => {                           // 手続き宣言部のコンパイル
+                    self.token = self.lex.next_token()?;
+                    self.func_decl(KindT::ProcId)?;
                 },
                 _ => { break; }                            // それ以外なら宣言部は終わり
             }
         }
+        for ti in self.gen.table.unresolved_forwards() {   // 前方宣言したものは同じ宣言部で定義するはず
+            let message = format!("{} is declared forward but never defined", self.gen.table.name(ti));
+            self.lex.error_at(self.gen.table.decl_span(ti), ErrorCode::UnresolvedForward, message.as_str())?;
+        }
         self.gen.back_patch(back_p as usize);                        // 内部関数を飛び越す命令にパッチ
         self.gen.table.change_v(p_index, self.gen.next_code());      // この関数の開始番地を修正
-        self.gen.gen_code_v(OpCode::Ict, self.gen.table.frame_l());  // このブロックの実行時の必要記憶域をとる命令
+        self.gen.set_span(self.token.span);
+        let ict = self.gen.gen_code_v(OpCode::Ict, self.gen.table.frame_l())?; // このブロックの実行時の必要記憶域をとる命令
 
-        self.statement();                  // このブロックの主文
-        self.gen.gen_code_r();             // リターン命令
+        self.statement()?;                 // このブロックの主文
+        self.gen.patch_value(ict as usize, self.gen.table.frame_l());  // 主文で作業用の変数が増えた分を反映
+        self.gen.set_span(self.token.span);
+        self.gen.gen_code_r()?;            // リターン命令
         self.gen.table.block_end();        // ブロックが終わったことをtableに連絡
+        Ok(())
     }
-    fn const_decl(&mut self) {                                // 定数宣言のコンパイル
+    fn const_decl(&mut self) -> Result<(), CompileError> {    // 定数宣言のコンパイル
         loop {
             if self.token.kind == KeyId::Id {
                 self.lex.set_id_kind(KindT::ConstId);                       // 印字のための情報のセット
                 let temp = self.token.clone();                              // 名前を入れておく
-                let next_token = self.lex.next_token().clone();
-                self.token = self.lex.check_get(next_token, KeyId::Equal);  // 次の名前は "=" のはず
+                let next_token = self.lex.next_token()?;
+                self.token = self.lex.check_get(next_token, KeyId::Equal)?;  // 次の名前は "=" のはず
 
-                if self.token.kind == KeyId::Num {
-                    let id = match temp.u {
-                        TokenContent::Id(s) => s,
-                        _ => unreachable!(),
-                    };
-                    let value = match self.token.u {
-                        TokenContent::Value(v) => v,
-                        _ => unreachable!(),
-                    };
-                    self.gen.table.enter_table_const(id, value);      // 定数名と値をテーブルに
-                } else {
-                    self.lex.error("number");
-                }
-                self.token = self.lex.next_token();
+                let id = match temp.u {
+                    TokenContent::Id(s) => s,
+                    _ => unreachable!(),
+                };
+                let value = self.const_expression()?.unwrap_or(0);  // 定数式の値（エラーなら0とする）
+                self.gen.table.enter_table_const(id, value, temp.span)?;  // 定数名と値をテーブルに
             } else {
-                self.lex.error("missing Identifier");
+                self.lex.error(ErrorCode::ExpectedIdentifier, "missing Identifier")?;
             }
             if self.token.kind != KeyId::Comma {                 // 次がコンマなら定数宣言が続く
                 if self.token.kind == KeyId::Id {                // 次が名前ならコンマを忘れたことにする
-                    self.lex.error(format!("insert {:?}", KeyId::Comma).as_str());
+                    self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Comma).as_str())?;
                     continue;
                 } else {
                     break;
                 }
             }
-            self.token = self.lex.next_token();
+            self.token = self.lex.next_token()?;
         }
         let token = self.token.clone();
-        self.token = self.lex.check_get(token, KeyId::Semicolon);  // 最後は ";" のはず
+        self.token = self.lex.check_get(token, KeyId::Semicolon)?;  // 最後は ";" のはず
+        Ok(())
+    }
+    fn const_expression(&mut self) -> Result<Option<i32>, CompileError> {  // 定数式をコンパイル時に計算する（エラーならNone）
+        let k = self.token.kind;
+        let mut value = if k == KeyId::Plus || k == KeyId::Minus {
+            let span = self.token.span;
+            self.token = self.lex.next_token()?;
+            let v = self.const_term()?;
+            if k == KeyId::Minus {
+                self.const_fold(span, Some(0), v, i32::checked_sub)?  // 符号反転は 0 - v
+            } else {
+                v
+            }
+        } else {
+            self.const_term()?
+        };
+        while self.token.kind == KeyId::Plus || self.token.kind == KeyId::Minus {
+            let (k, span) = (self.token.kind, self.token.span);  // 演算子とその位置
+            self.token = self.lex.next_token()?;
+            let v = self.const_term()?;
+            value = if k == KeyId::Plus {
+                self.const_fold(span, value, v, i32::checked_add)?
+            } else {
+                self.const_fold(span, value, v, i32::checked_sub)?
+            };
+        }
+        Ok(value)
+    }
+    fn const_term(&mut self) -> Result<Option<i32>, CompileError> {  // 定数式の項の計算
+        let mut value = self.const_factor()?;
+        while matches!(self.token.kind, KeyId::Mult | KeyId::Div | KeyId::Mod | KeyId::Percent) {
+            let (k, span) = (self.token.kind, self.token.span);  // 演算子とその位置
+            self.token = self.lex.next_token()?;
+            let v = self.const_factor()?;
+            if k != KeyId::Mult && v == Some(0) {
+                self.lex.error_at(span, ErrorCode::ConstantEvaluation, "division by zero in constant expression")?;
+                value = None;
+                continue;
+            }
+            value = match k {
+                KeyId::Mult => self.const_fold(span, value, v, i32::checked_mul)?,
+                KeyId::Div => self.const_fold(span, value, v, i32::checked_div)?,
+                _ => self.const_fold(span, value, v, |a, b| Some(a.wrapping_rem(b)))?,  // 実行時のmodと同じ
+            };
+        }
+        Ok(value)
     }
-    fn var_decl(&mut self) {                                  // 変数宣言のコンパイル
+    fn const_factor(&mut self) -> Result<Option<i32>, CompileError> {  // 定数式の因子の計算
+        let value = match self.token.kind {
+            KeyId::Num => match self.token.u {
+                TokenContent::Value(v) => Some(v),
+                _ => unreachable!(),
+            },
+            KeyId::Id => {                                    // 先に宣言した定数名
+                let id = match self.token.u.clone() {
+                    TokenContent::Id(s) => s,
+                    _ => unreachable!(),
+                };
+                match self.gen.table.search_t(&id) {
+                    Some(t_index) if self.gen.table.kind_t(t_index) == KindT::ConstId => {
+                        self.lex.set_id_kind(KindT::ConstId);
+                        Some(self.gen.table.val(t_index))
+                    },
+                    Some(t_index) => {
+                        self.lex.set_id_kind(self.gen.table.kind_t(t_index));
+                        self.lex.error(ErrorCode::KindMismatch, "kind error: constant")?;
+                        None
+                    },
+                    None => {
+                        self.search_id(id, self.token.span)?;   // 宣言されていない名前のエラー
+                        None
+                    },
+                }
+            },
+            KeyId::Lparen => {                                // 「(」「定数式」「)」
+                self.token = self.lex.next_token()?;
+                let value = self.const_expression()?;
+                let token = self.token.clone();
+                self.token = self.lex.check_get(token, KeyId::Rparen)?;
+                return Ok(value);
+            },
+            _ => {
+                self.lex.error(ErrorCode::ExpectedNumber, "number")?;
+                return Ok(None);
+            },
+        };
+        self.token = self.lex.next_token()?;
+        Ok(value)
+    }
+    fn const_fold(&mut self, span: Span, a: Option<i32>, b: Option<i32>, f: fn(i32, i32) -> Option<i32>)
+                  -> Result<Option<i32>, CompileError> {  // 定数式の演算、あふれたらエラー
+        match (a, b) {
+            (Some(a), Some(b)) => match f(a, b) {
+                Some(v) => Ok(Some(v)),
+                None => {
+                    self.lex.error_at(span, ErrorCode::ConstantEvaluation, "overflow in constant expression")?;
+                    Ok(None)
+                },
+            },
+            _ => Ok(None),
+        }
+    }
+    fn var_decl(&mut self) -> Result<(), CompileError> {      // 変数宣言のコンパイル
+        let mut names = Vec::new();                           // 型を読むまで登録を待つ名前（名前、位置、配列の要素数）
         loop {
             if self.token.kind == KeyId::Id {
                 self.lex.set_id_kind(KindT::VarId);           // 印字のための情報のセット
@@ -106,247 +245,745 @@ impl<'a, 'b, 'c, 'd> Compiler<'a, 'b, 'c, 'd> {
                     TokenContent::Id(s) => s,
                     _ => unreachable!(),
                 };
-                self.gen.table.enter_table_var(id);           // 変数名をテーブルに、番地はtableが決める
-                self.token = self.lex.next_token();
+                let id_span = self.token.span;
+                self.token = self.lex.next_token()?;
+                if self.token.kind == KeyId::Lbracket {       // "[" があれば配列の宣言
+                    let size = self.array_size()?;
+                    names.push((id, id_span, Some(size)));
+                } else {
+                    names.push((id, id_span, None));
+                }
             } else {
-                self.lex.error("missing Identifier");
+                self.lex.error(ErrorCode::ExpectedIdentifier, "missing Identifier")?;
             }
             if self.token.kind != KeyId::Comma {              // 次がコンマなら変数宣言が続く
                 if self.token.kind == KeyId::Id {             // 次が名前ならコンマを忘れたことにする
-                    self.lex.error(format!("insert {:?}", KeyId::Comma).as_str());
+                    self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Comma).as_str())?;
                     continue;
                 } else {
                     break;
                 }
             }
-            self.token = self.lex.next_token();
+            self.token = self.lex.next_token()?;
+        }
+        let ty = self.type_decl()?;                           // 並べた名前すべての型
+        for (id, id_span, size) in names {
+            match size {
+                Some(size) => self.gen.table.enter_table_array(id, size, ty, id_span)?,  // 配列名をテーブルに、要素数だけ番地をとる
+                None => self.gen.table.enter_table_var(id, ty, id_span)?,  // 変数名をテーブルに、番地はtableが決める
+            };
         }
         let token = self.token.clone();
-        self.token = self.lex.check_get(token, KeyId::Semicolon);  // 最後は ";" のはず
+        self.token = self.lex.check_get(token, KeyId::Semicolon)?;  // 最後は ";" のはず
+        Ok(())
     }
-    fn func_decl(&mut self) {                                 // 関数宣言のコンパイル
+    fn type_decl(&mut self) -> Result<Type, CompileError> {   // 「:」「型名」があればその型、なければinteger
+        if self.token.kind != KeyId::Colon {
+            return Ok(Type::Integer);
+        }
+        self.token = self.lex.next_token()?;
+        let ty = match self.token.kind {
+            KeyId::Integer => Type::Integer,
+            KeyId::Boolean => Type::Boolean,
+            _ => {
+                self.lex.error(ErrorCode::ExpectedType, "missing type name (integer or boolean)")?;
+                if self.token.kind == KeyId::Id {             // 知らない型名は読み捨てる
+                    self.token = self.lex.next_token()?;
+                }
+                return Ok(Type::Integer);
+            },
+        };
+        self.token = self.lex.next_token()?;
+        Ok(ty)
+    }
+    fn func_decl(&mut self, kind: KindT) -> Result<(), CompileError> {  // 関数・手続き宣言のコンパイル（kindはFuncIdかProcId）
         if self.token.kind == KeyId::Id {
-            self.lex.set_id_kind(KindT::FuncId);              // 印字のための情報のセット
+            self.lex.set_id_kind(kind);                       // 印字のための情報のセット
             let id = match self.token.u.clone() {
                 TokenContent::Id(s) => s,
                 _ => unreachable!(),
             };
-            let f_index = self.gen.table.enter_table_func(id, self.gen.next_code());  // 関数名をテーブルに登録。その先頭番地は、まず、次のコードの番地next_code()とする
-            let next_token = self.lex.next_token().clone();
-            self.token = self.lex.check_get(next_token, KeyId::Lparen);
-            self.gen.table.block_begin(FIRST_ADDR);           // パラメータ名のレベルは関数のブロックと同じ
-
-            loop {
-                if self.token.kind == KeyId::Id {             // パラメータ名がある場合、
-                    self.lex.set_id_kind(KindT::ParId);       // 印字のための情報をセット
-                    let id = match self.token.u.clone() {
-                        TokenContent::Id(s) => s,
-                        _ => unreachable!(),
-                    };
-                    self.gen.table.enter_table_par(id);       // パラメータ名をテーブルに登録
-                    self.token = self.lex.next_token();
-                } else {
-                    break;
-                }
-                if self.token.kind != KeyId::Comma {          // 次がコンマならパラメータ名が続く
-                    if self.token.kind == KeyId::Id {         // 次が名前ならコンマを忘れたことにする
-                        self.lex.error(format!("insert {:?}", KeyId::Comma).as_str());
-                        continue;
+            let id_span = self.token.span;
+            let forward = self.gen.table.search_forward(&id, kind);  // 前方宣言があれば、そのインデックス
+            let f_index = match kind {                        // 関数名をテーブルに登録。その先頭番地は、まず、次のコードの番地next_code()とする
+                KindT::FuncId => self.gen.table.enter_table_func(id, self.gen.next_code(), self.token.span)?,
+                KindT::ProcId => self.gen.table.enter_table_proc(id, self.gen.next_code(), self.token.span)?,
+                _ => unreachable!(),
+            };
+            let next_token = self.lex.next_token()?;
+            let has_pars = kind == KindT::FuncId || next_token.kind == KeyId::Lparen;  // パラメータのない手続きは () を省略できる
+            self.token = if has_pars {
+                self.lex.check_get(next_token, KeyId::Lparen)?
+            } else {
+                next_token
+            };
+            self.gen.table.block_begin(FIRST_ADDR)?;          // パラメータ名のレベルは関数のブロックと同じ
+
+            if has_pars {
+                loop {
+                    let by_ref = self.token.kind == KeyId::Var;   // "var" があればvarパラメータ
+                    if by_ref {
+                        self.token = self.lex.next_token()?;
+                    }
+                    if self.token.kind == KeyId::Id {             // パラメータ名がある場合、
+                        self.lex.set_id_kind(if by_ref { KindT::VarParId } else { KindT::ParId });  // 印字のための情報をセット
+                        let id = match self.token.u.clone() {
+                            TokenContent::Id(s) => s,
+                            _ => unreachable!(),
+                        };
+                        let id_span = self.token.span;
+                        self.token = self.lex.next_token()?;
+                        let size = if self.token.kind == KeyId::Lbracket {  // "[" があれば配列のパラメータ
+                            Some(self.array_size()?)
+                        } else {
+                            None
+                        };
+                        let ty = self.type_decl()?;               // パラメータ（配列なら要素）の型
+                        if let Some(size) = size {
+                            self.gen.table.enter_table_array_par(id, size, ty, id_span)?;  // 配列のパラメータ名をテーブルに登録（配列はいつも番地で渡す）
+                        } else if by_ref {
+                            self.gen.table.enter_table_var_par(id, ty, id_span)?;  // varパラメータ名をテーブルに登録
+                        } else {
+                            self.gen.table.enter_table_par(id, ty, id_span)?;  // パラメータ名をテーブルに登録
+                        }
                     } else {
+                        if by_ref {
+                            self.lex.error(ErrorCode::ExpectedIdentifier, "missing identifier")?;
+                        }
                         break;
                     }
+                    if self.token.kind != KeyId::Comma {          // 次がコンマならパラメータ名が続く
+                        if self.token.kind == KeyId::Id {         // 次が名前ならコンマを忘れたことにする
+                            self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Comma).as_str())?;
+                            continue;
+                        } else {
+                            break;
+                        }
+                    }
+                    self.token = self.lex.next_token()?;
                 }
-                self.token = self.lex.next_token();
+                let token = self.token.clone();
+                self.token = self.lex.check_get(token, KeyId::Rparen)?;  // 最後は ")" のはず
             }
-            let token = self.token.clone();
-            self.token = self.lex.check_get(token, KeyId::Rparen);  // 最後は ")" のはず
+            if self.token.kind == KeyId::Colon && kind == KindT::ProcId {  // 手続きは値を返さない
+                self.lex.error(ErrorCode::KindMismatch, "kind error: procedure has no result type")?;
+            }
+            let ty = self.type_decl()?;                             // 関数の値の型
+            self.gen.table.set_type(f_index, ty);
             self.gen.table.end_par();                               // パラメータ部が終わったことをテーブルに連絡
             if self.token.kind == KeyId::Semicolon {
-                println!("delete {:?}", KeyId::Semicolon);
-                self.token = self.lex.next_token();
+                let semicolon = self.token.span;
+                self.token = self.lex.next_token()?;
+                if self.token.kind != KeyId::Forward {              // 「;」「forward」以外の「;」は読み捨てる
+                    self.lex.warning_at(semicolon, ErrorCode::UnexpectedToken, format!("delete {:?}", KeyId::Semicolon).as_str());
+                }
+            } else if self.token.kind == KeyId::Forward {           // 「forward」の前には「;」が要る
+                let token = self.token.clone();
+                self.token = self.lex.check_get(token, KeyId::Semicolon)?;
             }
-            self.block(f_index);                     // ブロックのコンパイル、その関数名のインデックスを渡す
+            if self.token.kind == KeyId::Forward {                  // 前方宣言：ブロックはあとで定義する
+                if forward.is_some() {
+                    self.lex.error(ErrorCode::ForwardMismatch, "already declared forward")?;
+                }
+                self.token = self.lex.next_token()?;
+                self.gen.table.change_v(f_index, UNRESOLVED);       // 先頭番地は定義のときにパッチする
+                self.gen.table.enter_forward(f_index);
+                self.gen.table.block_end();                         // パラメータ名は見えなくする
+                let token = self.token.clone();
+                self.token = self.lex.check_get(token, KeyId::Semicolon)?;  // 最後は ";" のはず
+                return Ok(());
+            }
+            if let Some(forward) = forward {                        // 前方宣言の定義なら、それまでの呼び出しを定義へ向ける
+                if !self.gen.table.same_signature(forward, f_index) {
+                    let message = format!("{} does not match its forward declaration", self.gen.table.name(f_index));
+                    self.lex.error_at(id_span, ErrorCode::ForwardMismatch, message.as_str())?;
+                }
+                let addr = self.gen.table.rel_addr(f_index).addr;   // 定義の先頭番地（内部関数を飛び越すjmp命令）
+                for i in self.gen.table.resolve_forward(forward) {
+                    self.gen.patch_addr(i, addr);
+                }
+                self.gen.table.change_v(forward, addr);
+            }
+            self.block(f_index)?;                    // ブロックのコンパイル、その関数名のインデックスを渡す
             let token = self.token.clone();
-            self.token = self.lex.check_get(token, KeyId::Semicolon);  // 最後は ";" のはず
+            self.token = self.lex.check_get(token, KeyId::Semicolon)?;  // 最後は ";" のはず
         } else {
-            self.lex.error("missing identifier");
+            self.lex.error(ErrorCode::ExpectedIdentifier, "missing identifier")?;
         }
+        Ok(())
     }
-    fn statement(&mut self) {                                 // 文のコンパイル
+    fn statement(&mut self) -> Result<(), CompileError> {     // 文のコンパイル
         loop {
+            let span = self.token.span;                       // 文の先頭のトークンの位置
             match self.token.kind {
                 KeyId::Id => {                                // 代入文のコンパイル
                     let id = match self.token.u.clone() {
                         TokenContent::Id(s) => s,
                         _ => unreachable!(),
                     };
-                    let t_index = self.gen.table.search_t(id, KindT::VarId);  // 左辺の変数のインデックス
-                    let k = self.gen.table.kind_t(t_index);                   // 印字のための情報のセット
+                    let t_index = self.search_id(id, span)?;          // 左辺の変数のインデックス
+                    let k = self.gen.table.kind_t(t_index);           // 印字のための情報のセット
                     self.lex.set_id_kind(k);
-                    if k != KindT::VarId && k != KindT::ParId {       // 変数名かパラメータ名のはず
-                        self.lex.error("type error: var/par");
+                    if k == KindT::ProcId {                           // 手続き名なら手続き呼び出し文
+                        self.token = self.lex.next_token()?;
+                        self.call(t_index, span)?;
+                        return Ok(());
+                    }
+                    if k == KindT::ArrayId || k == KindT::ArrayParId {  // 配列の要素への代入文
+                        self.token = self.lex.next_token()?;
+                        self.gen.set_span(span);
+                        self.array_element(t_index)?;                 // 要素の番地の計算
+                        let token = self.token.clone();
+                        self.token = self.lex.check_get(token, KeyId::Assign)?;  // ":=" のはず
+                        self.typed_expression(self.gen.table.type_t(t_index))?;  // 要素の型の式のコンパイル
+                        self.gen.set_span(span);
+                        self.gen.gen_code_v(OpCode::Stx, self.gen.table.array_size(t_index))?;  // 要素への代入命令
+                        return Ok(());
+                    }
+                    if !is_variable(k) {                              // 変数名かパラメータ名のはず
+                        self.lex.error(ErrorCode::KindMismatch, "kind error: var/par")?;
+                    } else if self.loop_vars.contains(&t_index) {     // for文の制御変数には代入できない
+                        self.lex.error(ErrorCode::LoopVariableAssigned, "cannot assign to loop variable")?;
                     }
-                    let next_token = self.lex.next_token().clone();
-                    self.token = self.lex.check_get(next_token, KeyId::Assign);  // ":=" のはず
-                    self.expression();                                // 式のコンパイル
-                    self.gen.gen_code_t(OpCode::Sto, t_index);        // 左辺への代入命令
-                    return;
+                    let next_token = self.lex.next_token()?;
+                    self.token = self.lex.check_get(next_token, KeyId::Assign)?;  // ":=" のはず
+                    self.typed_expression(self.gen.table.type_t(t_index))?;  // 左辺の型の式のコンパイル
+                    self.gen.set_span(span);
+                    self.store(t_index)?;                             // 左辺への代入命令
+                    return Ok(());
                 },
                 KeyId::If => {                                // if文のコンパイル
-                    self.token = self.lex.next_token();
-                    self.condition();                         // 条件式のコンパイル
+                    self.token = self.lex.next_token()?;
+                    self.condition()?;                        // 条件式のコンパイル
                     let token = self.token.clone();
-                    self.token = self.lex.check_get(token, KeyId::Then);  // "then" のはず
-                    let back_p = self.gen.gen_code_v(OpCode::Jpc, 0);     // jpc命令
-                    self.statement();                         // 文のコンパイル
-                    self.gen.back_patch(back_p as usize);     // 上のjpc命令にバックパッチ
-                    return;
+                    self.token = self.lex.check_get(token, KeyId::Then)?;  // "then" のはず
+                    self.gen.set_span(span);
+                    let back_p = self.gen.gen_code_v(OpCode::Jpc, 0)?;    // jpc命令
+                    if self.token.kind != KeyId::Else {       // then のあとの文は空でもよい
+                        self.statement()?;                    // 文のコンパイル
+                    }
+                    if self.token.kind == KeyId::Else {       // else があれば、一番内側のifに対応させる
+                        self.gen.set_span(self.token.span);
+                        let back_p2 = self.gen.gen_code_v(OpCode::Jmp, 0)?;  // else部を飛び越すjmp命令
+                        self.gen.back_patch(back_p as usize); // 上のjpc命令にバックパッチ（else部へ）
+                        self.token = self.lex.next_token()?;
+                        self.statement()?;                    // else部の文のコンパイル
+                        self.gen.back_patch(back_p2 as usize);  // 上のjmp命令にバックパッチ
+                    } else {
+                        self.gen.back_patch(back_p as usize); // 上のjpc命令にバックパッチ
+                    }
+                    return Ok(());
+                },
+                KeyId::Else => {                              // ifのないelseはエラーにして読み捨て、続く文をコンパイル
+                    self.lex.error(ErrorCode::ElseWithoutIf, "else without matching if")?;
+                    self.token = self.lex.next_token()?;
+                    continue;
+                },
+                KeyId::Call => {                              // call文のコンパイル
+                    self.token = self.lex.next_token()?;
+                    if self.token.kind == KeyId::Id {
+                        let id = match self.token.u.clone() {
+                            TokenContent::Id(s) => s,
+                            _ => unreachable!(),
+                        };
+                        let t_index = self.search_id(id, self.token.span)?;  // 呼び出す手続きのインデックス
+                        let k = self.gen.table.kind_t(t_index);   // 印字のための情報のセット
+                        self.lex.set_id_kind(k);
+                        if k != KindT::ProcId {               // 手続き名のはず
+                            self.lex.error(ErrorCode::KindMismatch, "kind error: procedure")?;
+                        }
+                        self.token = self.lex.next_token()?;
+                        if k == KindT::ProcId || k == KindT::FuncId {  // 関数名でも呼び出しはコンパイルしておく
+                            self.call(t_index, span)?;
+                        }
+                    } else {
+                        self.lex.error(ErrorCode::ExpectedIdentifier, "missing identifier")?;
+                    }
+                    return Ok(());
                 },
                 KeyId::Ret => {                               // return文のコンパイル
-                    self.token = self.lex.next_token();
-                    self.expression();                        // 式のコンパイル
-                    self.gen.gen_code_r();                    // ret命令
-                    return;
+                    self.token = self.lex.next_token()?;
+                    let has_value = !matches!(self.token.kind,  // 文のあとに続くトークンでなければ返す値がある
+                        KeyId::End | KeyId::Semicolon | KeyId::Period | KeyId::Else | KeyId::Until);
+                    if self.gen.table.returns_value() {       // 関数なら返す値が要る
+                        if has_value {
+                            self.typed_expression(self.gen.table.return_type())?;  // 関数の値の型の式のコンパイル
+                        } else {
+                            self.lex.error(ErrorCode::ReturnMismatch, "missing return value")?;
+                        }
+                    } else if has_value {                     // 手続きと主ブロックは値を返さない
+                        self.lex.error(ErrorCode::ReturnMismatch, "procedure cannot return a value")?;
+                        self.or_condition()?;                 // 式は読み飛ばさずにコンパイルしておく
+                    }
+                    self.gen.set_span(span);
+                    self.gen.gen_code_r()?;                   // ret命令
+                    return Ok(());
                 },
                 KeyId::Begin => {                             // begin . . end文のコンパイル
-                    self.token = self.lex.next_token();
+                    self.token = self.lex.next_token()?;
                     loop {
-                        self.statement();                     // 文のコンパイル
+                        self.statement()?;                    // 文のコンパイル
                         loop {
                             if self.token.kind == KeyId::Semicolon {  // 次が ";" なら文が続く
-                                self.token = self.lex.next_token();
+                                self.token = self.lex.next_token()?;
                                 break;
                             }
                             if self.token.kind == KeyId::End {        // 次がendなら終わり
-                                self.token = self.lex.next_token();
-                                return;
+                                self.token = self.lex.next_token()?;
+                                return Ok(());
+                            }
+                            if self.token.kind == KeyId::Period {     // ピリオドの先は読めないので、endを忘れたことにする
+                                self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::End).as_str())?;
+                                return Ok(());
                             }
                             if self.token.is_st_begin_key() {         // 次が文の先頭記号なら ";" を忘れたことにする
-                                self.lex.error(format!("insert {:?}", KeyId::Semicolon).as_str());
+                                self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Semicolon).as_str())?;
                                 break;
                             }
-                            println!("delete {:?}", self.token.kind);  // それ以外ならエラーとして読み捨てる
-                            self.token = self.lex.next_token();
+                            self.lex.warning(ErrorCode::UnexpectedToken,  // それ以外ならエラーとして読み捨てる
+                                             format!("delete {:?}", self.token.kind).as_str());
+                            self.token = self.lex.next_token()?;
                         }
                     }
                 },
+                KeyId::For => {                               // for文のコンパイル
+                    self.for_statement(span)?;
+                    return Ok(());
+                },
                 KeyId::While => {                             // while文のコンパイル
-                    self.token = self.lex.next_token();
+                    self.token = self.lex.next_token()?;
                     let back_p2 = self.gen.next_code();       // while文の最後のjmp命令の飛び先
-                    self.condition();                         // 条件式のコンパイル
+                    self.condition()?;                        // 条件式のコンパイル
                     let token = self.token.clone();
-                    self.token = self.lex.check_get(token, KeyId::Do);  // "do" のはず
-                    let back_p = self.gen.gen_code_v(OpCode::Jpc, 0);   // 条件式が偽のとき飛び出すjpc命令
-                    self.statement();                           // 文のコンパイル
-                    self.gen.gen_code_v(OpCode::Jmp, back_p2);  // while文の先頭へのjmp命令
+                    self.token = self.lex.check_get(token, KeyId::Do)?;  // "do" のはず
+                    self.gen.set_span(span);
+                    let back_p = self.gen.gen_code_v(OpCode::Jpc, 0)?;  // 条件式が偽のとき飛び出すjpc命令
+                    self.loops.push(LoopPatches::default());
+                    self.statement()?;                          // 文のコンパイル
+                    self.gen.set_span(span);
+                    self.gen.gen_code_v(OpCode::Jmp, back_p2)?; // while文の先頭へのjmp命令
                     self.gen.back_patch(back_p as usize);     // 偽のとき飛び出すjpc命令へのバックパッチ
-                    return;
+                    self.end_loop(back_p2);                   // continueは条件式へ
+                    return Ok(());
                 },
-                KeyId::Write => {                             // write文のコンパイル
-                    self.token = self.lex.next_token();
-                    self.expression();                        // 式のコンパイル
-                    self.gen.gen_code_o(Operator::Wrt);       // その値を出力するwrt命令
-                    return;
+                KeyId::Write | KeyId::WriteLn => {            // write文・writeln文のコンパイル
+                    let newline = self.token.kind == KeyId::WriteLn;
+                    self.token = self.lex.next_token()?;
+                    if self.token.kind == KeyId::Lparen {     // 「(」「出力するもの」「,」. . .「)」
+                        self.write_list()?;
+                    } else if !newline {                      // 括弧のないwriteは１つだけ出力する
+                        self.write_item()?;
+                    }
+                    if newline {
+                        self.gen.set_span(span);
+                        self.gen.gen_code_o(Operator::Wrl)?;  // 改行を出力するwrl命令
+                    }
+                    return Ok(());
+                },
+                KeyId::Read => {                              // read文のコンパイル
+                    self.token = self.lex.next_token()?;
+                    if self.token.kind == KeyId::Id {
+                        let id = match self.token.u.clone() {
+                            TokenContent::Id(s) => s,
+                            _ => unreachable!(),
+                        };
+                        let id_span = self.token.span;
+                        let t_index = self.search_id(id, id_span)?;   // 読み込んだ値を入れる変数のインデックス
+                        let k = self.gen.table.kind_t(t_index);   // 印字のための情報のセット
+                        self.lex.set_id_kind(k);
+                        if k != KindT::ConstId {              // 読み込めるのは整数だけ
+                            self.check_type(id_span, Type::Integer, self.gen.table.type_t(t_index))?;
+                        }
+                        if k == KindT::ArrayId || k == KindT::ArrayParId {  // 配列の要素に読み込む場合
+                            self.token = self.lex.next_token()?;
+                            self.gen.set_span(span);
+                            self.array_element(t_index)?;     // 要素の番地の計算
+                            self.gen.set_span(span);
+                            self.gen.gen_code_o(Operator::Rd)?;  // 整数を１つ読むrd命令
+                            self.gen.gen_code_v(OpCode::Stx, self.gen.table.array_size(t_index))?;  // 要素への代入命令
+                            return Ok(());
+                        }
+                        if !is_variable(k) {                  // 変数名かパラメータ名のはず
+                            self.lex.error(ErrorCode::KindMismatch, "kind error: var/par")?;
+                        } else if self.loop_vars.contains(&t_index) {  // for文の制御変数には代入できない
+                            self.lex.error(ErrorCode::LoopVariableAssigned, "cannot assign to loop variable")?;
+                        }
+                        self.gen.set_span(span);
+                        self.gen.gen_code_o(Operator::Rd)?;   // 整数を１つ読むrd命令
+                        self.store(t_index)?;                 // 変数への代入命令
+                        self.token = self.lex.next_token()?;
+                    } else {
+                        self.lex.error(ErrorCode::ExpectedIdentifier, "missing identifier")?;
+                    }
+                    return Ok(());
+                },
+                KeyId::Repeat => {                            // repeat . . until文のコンパイル
+                    self.token = self.lex.next_token()?;
+                    let back_p = self.gen.next_code();        // 条件が偽のときに戻る先頭番地
+                    self.loops.push(LoopPatches::default());
+                    loop {
+                        self.statement()?;                    // 文のコンパイル
+                        loop {
+                            if self.token.kind == KeyId::Semicolon {  // 次が ";" なら文が続く
+                                self.token = self.lex.next_token()?;
+                                break;
+                            }
+                            if matches!(self.token.kind, KeyId::Until | KeyId::End | KeyId::Period) {  // 次がuntil（かそれを忘れた）なら文は終わり
+                                break;
+                            }
+                            if self.token.is_st_begin_key() {         // 次が文の先頭記号なら ";" を忘れたことにする
+                                self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Semicolon).as_str())?;
+                                break;
+                            }
+                            self.lex.warning(ErrorCode::UnexpectedToken,  // それ以外ならエラーとして読み捨てる
+                                             format!("delete {:?}", self.token.kind).as_str());
+                            self.token = self.lex.next_token()?;
+                        }
+                        if matches!(self.token.kind, KeyId::Until | KeyId::End | KeyId::Period) {
+                            break;
+                        }
+                    }
+                    let cont = self.gen.next_code();          // continueは条件式へ
+                    if self.token.kind != KeyId::Until {      // untilを忘れたときは、条件が真だったことにして抜ける
+                        self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Until).as_str())?;
+                        self.end_loop(cont);
+                        return Ok(());
+                    }
+                    self.token = self.lex.next_token()?;
+                    self.condition()?;                        // 条件式のコンパイル
+                    self.gen.set_span(span);
+                    self.gen.gen_code_v(OpCode::Jpc, back_p)?;  // 条件式が偽なら先頭へ戻るjpc命令
+                    self.end_loop(cont);
+                    return Ok(());
                 },
-                KeyId::WriteLn => {                           // writeln文のコンパイル
-                    self.token = self.lex.next_token();
-                    self.gen.gen_code_o(Operator::Wrl);       // 改行を出力するwrl命令
-                    return;
+                KeyId::Break | KeyId::Continue => {           // break文・continue文のコンパイル
+                    self.gen.set_span(span);
+                    if self.loops.is_empty() {                // 繰り返しの中でしか使えない
+                        let word = if self.token.kind == KeyId::Break { "break" } else { "continue" };
+                        self.lex.error(ErrorCode::OutsideLoop, format!("{} outside of a loop", word).as_str())?;
+                    } else {
+                        let jmp = self.gen.gen_code_v(OpCode::Jmp, 0)?;  // 飛び先はあとでバックパッチ
+                        let patches = self.loops.last_mut().unwrap();
+                        if self.token.kind == KeyId::Break {
+                            patches.breaks.push(jmp);
+                        } else {
+                            patches.continues.push(jmp);
+                        }
+                    }
+                    self.token = self.lex.next_token()?;
+                    return Ok(());
                 },
-                KeyId::End | KeyId::Semicolon | KeyId::Period => {  // Follow statement のトークンの場合
-                    return;                                         // 空文を読んだことにして終わり
+                KeyId::End | KeyId::Semicolon | KeyId::Period | KeyId::Until => {  // Follow statement のトークンの場合
+                    return Ok(());                                  // 空文を読んだことにして終わり
                 },
                 _ => {                                         // 文の先頭のキーまで読み捨てる
-                    println!("delete {:?}", self.token.kind);  // 今読んだトークンを読み捨てる
-                    self.token = self.lex.next_token();
+                    self.lex.warning(ErrorCode::UnexpectedToken,  // 今読んだトークンを読み捨てる
+                                     format!("delete {:?}", self.token.kind).as_str());
+                    self.token = self.lex.next_token()?;
                     continue;
                 }
             }
         }
     }
-    fn expression(&mut self) {                                // 式のコンパイル
-        let mut k = self.token.kind.clone();
+    fn for_statement(&mut self, span: Span) -> Result<(), CompileError> {  // for文のコンパイル（"for" を読んだところで呼ばれる）
+        self.token = self.lex.next_token()?;
+        let mut var = None;                                   // 制御変数のインデックス
+        if self.token.kind == KeyId::Id {
+            let id = match self.token.u.clone() {
+                TokenContent::Id(s) => s,
+                _ => unreachable!(),
+            };
+            let t_index = self.search_id(id, self.token.span)?;
+            let k = self.gen.table.kind_t(t_index);           // 印字のための情報のセット
+            self.lex.set_id_kind(k);
+            if k != KindT::VarId && k != KindT::ParId {       // 変数名かパラメータ名のはず
+                self.lex.error(ErrorCode::KindMismatch, "kind error: var/par")?;
+            } else if self.loop_vars.contains(&t_index) {     // 外側のfor文の制御変数は使えない
+                self.lex.error(ErrorCode::LoopVariableAssigned, "cannot assign to loop variable")?;
+            } else if self.gen.table.type_t(t_index) != Type::Integer {  // 制御変数は整数のはず
+                self.check_type(self.token.span, Type::Integer, self.gen.table.type_t(t_index))?;
+            } else {
+                var = Some(t_index);
+            }
+            self.token = self.lex.next_token()?;
+        } else {
+            self.lex.error(ErrorCode::ExpectedIdentifier, "missing identifier")?;
+        }
+        let token = self.token.clone();
+        self.token = self.lex.check_get(token, KeyId::Assign)?;  // ":=" のはず
+        self.typed_expression(Type::Integer)?;                // 初期値の式のコンパイル、値は終値のあとで代入する
+        let down = self.token.kind == KeyId::Downto;
+        if down {
+            self.token = self.lex.next_token()?;
+        } else {
+            let token = self.token.clone();
+            self.token = self.lex.check_get(token, KeyId::To)?;  // "to" か "downto" のはず
+        }
+        self.typed_expression(Type::Integer)?;                // 終値の式のコンパイル、値は一度だけ計算する
+        self.gen.set_span(span);
+        let limit = self.gen.table.enter_table_temp(span)?;   // 終値を入れておく作業用の変数
+        self.gen.gen_code_t(OpCode::Sto, limit)?;
+        let var = match var {
+            Some(t_index) => t_index,
+            None => self.gen.table.enter_table_temp(span)?,   // エラーのときは作業用の変数で代用
+        };
+        self.gen.gen_code_t(OpCode::Sto, var)?;               // 制御変数に初期値を代入
+        let token = self.token.clone();
+        self.token = self.lex.check_get(token, KeyId::Do)?;   // "do" のはず
+
+        let (enter, more, step) = if down {                   // 入る条件、続ける条件、増分の演算
+            (Operator::Greq, Operator::Gr, Operator::Sub)
+        } else {
+            (Operator::Lseq, Operator::Ls, Operator::Add)
+        };
+        self.gen.set_span(span);
+        self.gen.gen_code_t(OpCode::Lod, var)?;
+        self.gen.gen_code_t(OpCode::Lod, limit)?;
+        self.gen.gen_code_o(enter)?;
+        let back_p = self.gen.gen_code_v(OpCode::Jpc, 0)?;    // 一度も実行しないときに飛び出すjpc命令
+        let body = self.gen.next_code();                      // 本体の先頭番地
+        self.loop_vars.push(var);
+        self.loops.push(LoopPatches::default());
+        self.statement()?;                                    // 本体の文のコンパイル
+        self.loop_vars.pop();
+        self.gen.set_span(span);
+        let cont = self.gen.next_code();                      // continueは終値との比較へ
+        self.gen.gen_code_t(OpCode::Lod, var)?;               // 終値に達していれば、増やす前に飛び出す
+        self.gen.gen_code_t(OpCode::Lod, limit)?;             // （終値が最大の整数でもあふれない）
+        self.gen.gen_code_o(more)?;
+        let back_p2 = self.gen.gen_code_v(OpCode::Jpc, 0)?;
+        self.gen.gen_code_t(OpCode::Lod, var)?;
+        self.gen.gen_code_v(OpCode::Lit, 1)?;
+        self.gen.gen_code_o(step)?;
+        self.gen.gen_code_t(OpCode::Sto, var)?;
+        self.gen.gen_code_v(OpCode::Jmp, body)?;              // 本体の先頭へのjmp命令
+        self.gen.back_patch(back_p as usize);
+        self.gen.back_patch(back_p2 as usize);
+        self.end_loop(cont);
+        Ok(())
+    }
+    fn write_list(&mut self) -> Result<(), CompileError> {    // 出力するものの並びのコンパイル（"(" を読んだところで呼ばれる）
+        let paren_span = self.token.span;
+        self.token = self.lex.next_token()?;
+        if self.token.kind == KeyId::Rparen {                 // 出力するものがない
+            self.token = self.lex.next_token()?;
+            return Ok(());
+        }
+        if !matches!(self.token.u, TokenContent::Str(_)) {    // 最初が式なら、")" のあとに演算子が続くかもしれない
+            let span = self.token.span;
+            let ty = self.or_condition()?;
+            if self.token.kind == KeyId::Rparen {             // 出力するものが１つだけのときは、
+                self.token = self.lex.next_token()?;
+                let ty = self.rest_of_expression(paren_span, ty)?;  // 括弧で囲んだ式を因子とする式の残りを読む
+                return self.write_value(paren_span, ty);
+            }
+            self.write_value(span, ty)?;
+            if self.token.kind == KeyId::Comma {
+                self.token = self.lex.next_token()?;
+            } else {
+                let token = self.token.clone();
+                self.token = self.lex.check_get(token, KeyId::Rparen)?;
+                return Ok(());
+            }
+        }
+        loop {
+            self.write_item()?;
+            if self.token.kind != KeyId::Comma {              // 次がコンマなら出力するものが続く
+                break;
+            }
+            self.token = self.lex.next_token()?;
+        }
+        let token = self.token.clone();
+        self.token = self.lex.check_get(token, KeyId::Rparen)?;  // 最後は ")" のはず
+        Ok(())
+    }
+    fn write_item(&mut self) -> Result<(), CompileError> {    // 出力するもの「式か文字列」「:」「桁数」のコンパイル
+        let span = self.token.span;
+        if let TokenContent::Str(s) = self.token.u.clone() {  // 文字列定数
+            self.token = self.lex.next_token()?;
+            let width = self.field_width()?.unwrap_or(0);     // 桁数がなければ詰めない
+            let index = self.gen.enter_string(s);
+            self.gen.set_span(span);
+            self.gen.gen_code_s(index, width)?;               // 文字列を右寄せで出力するwrs命令
+            return Ok(());
+        }
+        let ty = self.or_condition()?;
+        self.write_value(span, ty)
+    }
+    fn write_value(&mut self, span: Span, ty: Type) -> Result<(), CompileError> {  // 積んだ式の値と「:」「桁数」の出力（spanは式の位置）
+        self.check_type(span, Type::Integer, ty)?;            // 出力できるのは整数か文字列だけ
+        match self.field_width()? {
+            Some(width) => {
+                self.gen.set_span(span);
+                self.gen.gen_code_v(OpCode::Lit, width)?;
+                self.gen.gen_code_o(Operator::Wrf)?;          // 桁数を指定して出力するwrf命令
+            },
+            None => {
+                self.gen.set_span(span);
+                self.gen.gen_code_o(Operator::Wrt)?;          // その値を出力するwrt命令
+            },
+        }
+        Ok(())
+    }
+    fn field_width(&mut self) -> Result<Option<i32>, CompileError> {  // 「:」「桁数」があれば桁数を返す（桁数は定数式）
+        if self.token.kind != KeyId::Colon {
+            return Ok(None);
+        }
+        self.token = self.lex.next_token()?;
+        let span = self.token.span;
+        match self.const_expression()? {
+            Some(width) if width < 0 => {
+                self.lex.error_at(span, ErrorCode::InvalidFieldWidth, "field width must not be negative")?;
+                Ok(None)
+            },
+            Some(width) if width > MAX_WIDTH => {
+                self.lex.error_at(span, ErrorCode::InvalidFieldWidth,
+                                  format!("field width too large (limit is {})", MAX_WIDTH).as_str())?;
+                Ok(None)
+            },
+            Some(width) => Ok(Some(width)),
+            None => Ok(None),                                 // エラーは定数式の計算で報告済み
+        }
+    }
+    fn end_loop(&mut self, cont: i32) {                       // 繰り返しの終わりで、break・continueのjmp命令にバックパッチ
+        let patches = self.loops.pop().unwrap();
+        for i in patches.breaks {                             // breakは次の命令語へ
+            self.gen.back_patch(i as usize);
+        }
+        for i in patches.continues {                          // continueはcontへ
+            self.gen.patch_value(i as usize, cont);
+        }
+    }
+    fn typed_expression(&mut self, expected: Type) -> Result<(), CompileError> {  // 型expectedの式のコンパイル
+        let span = self.token.span;                           // 式の先頭のトークンの位置
+        let ty = self.or_condition()?;
+        self.check_type(span, expected, ty)
+    }
+    fn check_type(&mut self, span: Span, expected: Type, actual: Type) -> Result<(), CompileError> {  // 型が違えばエラー
+        if actual != expected {
+            self.lex.error_at(span, ErrorCode::TypeMismatch,
+                              format!("type error: expected {}, found {}", expected, actual).as_str())?;
+        }
+        Ok(())
+    }
+    fn expression(&mut self) -> Result<Type, CompileError> {  // 式のコンパイル、式の型を返す
+        let k = self.token.kind;
+        let span = self.token.span;                           // 符号の位置
+        let ty;
         if k == KeyId::Plus || k == KeyId::Minus {
-            self.token = self.lex.next_token();
-            self.term();
+            self.token = self.lex.next_token()?;
+            let term_span = self.token.span;
+            let term_ty = self.term()?;
+            self.check_type(term_span, Type::Integer, term_ty)?;  // 符号は整数にだけ付けられる
             if k == KeyId::Minus {
-                self.gen.gen_code_o(Operator::Neg);
+                self.gen.set_span(span);
+                self.gen.gen_code_o(Operator::Neg)?;
             }
+            ty = Type::Integer;
         } else {
-            self.term();
+            ty = self.term()?;
         }
-        k = self.token.kind.clone();
+        self.rest_of_expression_terms(span, ty)
+    }
+    fn rest_of_expression_terms(&mut self, mut span: Span, mut ty: Type) -> Result<Type, CompileError> {  // 最初の項を読んだあとの式の残り
+        let mut k = self.token.kind;
         while k == KeyId::Plus || k == KeyId::Minus {
-            self.token = self.lex.next_token();
-            self.term();
+            self.check_type(span, Type::Integer, ty)?;          // 加減算は整数どうし
+            span = self.token.span;
+            self.token = self.lex.next_token()?;
+            let term_span = self.token.span;
+            let term_ty = self.term()?;
+            self.check_type(term_span, Type::Integer, term_ty)?;
+            self.gen.set_span(span);
             if k == KeyId::Minus {
-                self.gen.gen_code_o(Operator::Sub);
+                self.gen.gen_code_o(Operator::Sub)?;
             } else {
-                self.gen.gen_code_o(Operator::Add);
+                self.gen.gen_code_o(Operator::Add)?;
             }
-            k = self.token.kind.clone();
+            ty = Type::Integer;
+            k = self.token.kind;
         }
+        Ok(ty)
     }
-    fn term(&mut self) {                                      // 式の項のコンパイル
-        self.factor();
-        let mut k = self.token.kind.clone();
-        while k == KeyId::Mult || k == KeyId::Div {
-            self.token = self.lex.next_token();
-            self.factor();
-            if k == KeyId::Mult {
-                self.gen.gen_code_o(Operator::Mul);
-            } else {
-                self.gen.gen_code_o(Operator::Div);
-            }
-            k = self.token.kind.clone();
+    fn term(&mut self) -> Result<Type, CompileError> {        // 式の項のコンパイル
+        let factor_span = self.token.span;                    // 左の因子の位置
+        let ty = self.factor()?;
+        self.rest_of_term(factor_span, ty)
+    }
+    fn rest_of_term(&mut self, mut factor_span: Span, mut ty: Type) -> Result<Type, CompileError> {  // 最初の因子を読んだあとの項の残り
+        let mut k = self.token.kind;
+        while matches!(k, KeyId::Mult | KeyId::Div | KeyId::Mod | KeyId::Percent) {
+            self.check_type(factor_span, Type::Integer, ty)?;  // 乗除算は整数どうし
+            let span = self.token.span;                       // 演算子の位置
+            self.token = self.lex.next_token()?;
+            factor_span = self.token.span;
+            let factor_ty = self.factor()?;
+            self.check_type(factor_span, Type::Integer, factor_ty)?;
+            self.gen.set_span(span);
+            match k {
+                KeyId::Mult => self.gen.gen_code_o(Operator::Mul)?,
+                KeyId::Div => self.gen.gen_code_o(Operator::Div)?,
+                _ => self.gen.gen_code_o(Operator::Mod)?,     // "mod" と "%" は同じ
+            };
+            ty = Type::Integer;
+            k = self.token.kind;
         }
+        Ok(ty)
     }
-    fn factor(&mut self) {                                    // 式の因子のコンパイル
+    fn rest_of_expression(&mut self, span: Span, ty: Type) -> Result<Type, CompileError> {  // 因子（型ty）を読んだあとの式の残り
+        let ty = self.rest_of_term(span, ty)?;
+        let ty = self.rest_of_expression_terms(span, ty)?;
+        let ty = self.rest_of_relation(span, ty)?;
+        let ty = self.rest_of_and(span, ty)?;
+        self.rest_of_or(span, ty)
+    }
+    fn factor(&mut self) -> Result<Type, CompileError> {      // 式の因子のコンパイル
+        let span = self.token.span;                           // 因子の先頭のトークンの位置
+        let mut ty = Type::Integer;                           // エラーの場合は整数とする
+        self.gen.set_span(span);
         if self.token.kind == KeyId::Id {
             let id = match self.token.u.clone() {
                 TokenContent::Id(s) => s,
                 _ => unreachable!(),
             };
-            let t_index = self.gen.table.search_t(id, KindT::VarId);
+            let t_index = self.search_id(id, span)?;
             let k = self.gen.table.kind_t(t_index);           // 印字のための情報のセット
             self.lex.set_id_kind(k);
             match k {
                 KindT::VarId | KindT::ParId => {              // 変数名かパラメータ名
-                    self.gen.gen_code_t(OpCode::Lod, t_index);
-                    self.token = self.lex.next_token();
+                    self.gen.gen_code_t(OpCode::Lod, t_index)?;
+                    self.token = self.lex.next_token()?;
+                    ty = self.gen.table.type_t(t_index);
+                },
+                KindT::VarParId => {                          // varパラメータ名（指す変数の値）
+                    self.gen.gen_code_t(OpCode::Ldi, t_index)?;
+                    self.token = self.lex.next_token()?;
+                    ty = self.gen.table.type_t(t_index);
                 },
                 KindT::ConstId => {                           // 定数名
-                    self.gen.gen_code_v(OpCode::Lit, self.gen.table.val(t_index));
-                    self.token = self.lex.next_token();
+                    self.gen.gen_code_v(OpCode::Lit, self.gen.table.val(t_index))?;
+                    self.token = self.lex.next_token()?;
                 },
                 KindT::FuncId => {                            // 関数呼び出し
-                    self.token = self.lex.next_token();
-                    if self.token.kind == KeyId::Lparen {
-                        let mut i = 0;                        // iは実引数の個数
-                        self.token = self.lex.next_token();
-                        if self.token.kind != KeyId::Rparen {
-                            loop {
-                                self.expression();            // 実引数のコンパイル
-                                i += 1;
-                                if self.token.kind == KeyId::Comma {  // 次がコンマなら実引数が続く
-                                    self.token = self.lex.next_token();
-                                    continue;
-                                }
-                                let token = self.token.clone();
-                                self.token = self.lex.check_get(token, KeyId::Rparen);
-                                break;
-                            }
-                        } else {
-                            self.token = self.lex.next_token();
-                        }
-                        if self.gen.table.pars(t_index) != i {  // pars(t_index) は仮引数の個数
-                            self.lex.error("unmatched par");
-                        }
-                    } else {
-                        self.lex.error(format!("insert {:?}", KeyId::Lparen).as_str());
-                        self.lex.error(format!("insert {:?}", KeyId::Rparen).as_str());
+                    self.token = self.lex.next_token()?;
+                    self.call(t_index, span)?;
+                    ty = self.gen.table.type_t(t_index);      // 関数の値の型
+                },
+                KindT::ProcId => {                            // 手続きは値を返さないのでエラー
+                    self.lex.error(ErrorCode::KindMismatch, "kind error: procedure has no value")?;
+                    self.token = self.lex.next_token()?;
+                    self.call(t_index, span)?;
+                },
+                KindT::ArrayId | KindT::ArrayParId => {       // 配列の要素
+                    self.token = self.lex.next_token()?;
+                    if self.token.kind == KeyId::Lbracket {
+                        self.array_element(t_index)?;         // 要素の番地の計算
+                        self.gen.set_span(span);
+                        self.gen.gen_code_v(OpCode::Ldx, self.gen.table.array_size(t_index))?;
+                    } else {                                  // 配列そのものは値ではない
+                        self.lex.error_at(span, ErrorCode::KindMismatch, "kind error: array needs an index")?;
                     }
-                    self.gen.gen_code_t(OpCode::Cal, t_index);  // call命令
+                    ty = self.gen.table.type_t(t_index);      // 要素の型
                 }
             }
         } else if self.token.kind == KeyId::Num {             // 定数
@@ -354,55 +991,752 @@ impl<'a, 'b, 'c, 'd> Compiler<'a, 'b, 'c, 'd> {
                 TokenContent::Value(v) => v,
                 _ => unreachable!(),
             };
-            self.gen.gen_code_v(OpCode::Lit, value);
-            self.token = self.lex.next_token();
-        } else if self.token.kind == KeyId::Lparen {          // 「(」「因子」「)」
-            self.token = self.lex.next_token();
-            self.expression();
+            self.gen.gen_code_v(OpCode::Lit, value)?;
+            self.token = self.lex.next_token()?;
+        } else if self.token.kind == KeyId::True || self.token.kind == KeyId::False {  // 真偽値の定数（真は1、偽は0）
+            let value = if self.token.kind == KeyId::True { 1 } else { 0 };
+            self.gen.gen_code_v(OpCode::Lit, value)?;
+            self.token = self.lex.next_token()?;
+            ty = Type::Boolean;
+        } else if self.token.kind == KeyId::Str {             // 文字列はwrite文でしか使えない
+            self.lex.error(ErrorCode::TypeMismatch, "type error: string can only be written")?;
+            self.gen.gen_code_v(OpCode::Lit, 0)?;
+            self.token = self.lex.next_token()?;
+        } else if self.token.kind == KeyId::Lparen {          // 「(」「式」「)」
+            self.token = self.lex.next_token()?;
+            ty = self.or_condition()?;                        // 括弧の中は条件でもよい
             let token = self.token.clone();
-            self.token = self.lex.check_get(token, KeyId::Rparen);
+            self.token = self.lex.check_get(token, KeyId::Rparen)?;
+        } else {                                              // 因子がない（単項の "-" なども因子にはならない）
+            self.lex.error(ErrorCode::ExpectedNumber, format!("missing factor: {:?}", self.token.kind).as_str())?;
+            self.gen.gen_code_v(OpCode::Lit, 0)?;             // スタックの釣り合いをとるため0を積んでおく
         }
         match self.token.kind {                               // 因子のあとがまた因子ならエラー
-            KeyId::Id | KeyId::Num | KeyId::Lparen => {
-                self.lex.error(format!("missing operator: {:?}", self.token.kind).as_str());
-                self.factor();
+            KeyId::Id | KeyId::Num | KeyId::Str | KeyId::True | KeyId::False | KeyId::Lparen => {
+                self.lex.error(ErrorCode::MissingOperator, format!("missing operator: {:?}", self.token.kind).as_str())?;
+                self.factor()?;
             },
             _ => (),
         }
+        Ok(ty)
     }
-    fn condition(&mut self) {                                 // 条件式のコンパイル
-        if self.token.kind == KeyId::Odd {
-            self.token = self.lex.next_token();
-            self.expression();
-            self.gen.gen_code_o(Operator::Odd);
-        } else {
-            self.expression();
-            let k = self.token.kind.clone();
-            match k {
-                KeyId::Equal | KeyId::Lss | KeyId::Gtr | KeyId::NotEq | KeyId::LssEq | KeyId::GtrEq => {},
-                _ => {
-                    self.lex.error("type error: rel-op");
+    fn call(&mut self, t_index: i32, span: Span) -> Result<(), CompileError> {  // 関数・手続きの実引数と呼び出しのコンパイル（名前は読んだあと）
+        if self.token.kind == KeyId::Lparen {
+            let mut i = 0;                                    // iは実引数の個数
+            self.token = self.lex.next_token()?;
+            if self.token.kind != KeyId::Rparen {
+                loop {
+                    match self.gen.table.par_mode(t_index, i as usize) {
+                        Some((ParMode::Array(size), ty)) => self.array_arg(size, ty)?,  // 配列のパラメータには配列を渡す
+                        Some((ParMode::Var, ty)) => self.var_arg(ty)?,  // varパラメータには変数の番地を渡す
+                        Some((ParMode::Value, ty)) => self.typed_expression(ty)?,  // 実引数のコンパイル
+                        None => { self.or_condition()?; },    // 多すぎる実引数もコンパイルしておく
+                    }
+                    i += 1;
+                    if self.token.kind == KeyId::Comma {      // 次がコンマなら実引数が続く
+                        self.token = self.lex.next_token()?;
+                        continue;
+                    }
+                    let token = self.token.clone();
+                    self.token = self.lex.check_get(token, KeyId::Rparen)?;
+                    break;
                 }
+            } else {
+                self.token = self.lex.next_token()?;
             }
-            self.token = self.lex.next_token();
-            self.expression();
-            match k {
-                KeyId::Equal => self.gen.gen_code_o(Operator::Eq),
-                KeyId::Lss => self.gen.gen_code_o(Operator::Ls),
-                KeyId::Gtr => self.gen.gen_code_o(Operator::Gr),
-                KeyId::NotEq => self.gen.gen_code_o(Operator::Neq),
-                KeyId::LssEq => self.gen.gen_code_o(Operator::Lseq),
-                KeyId::GtrEq => self.gen.gen_code_o(Operator::Greq),
-                _ => unreachable!(),
-            };
+            if self.gen.table.pars(t_index) != i {            // pars(t_index) は仮引数の個数
+                self.lex.error(ErrorCode::ArgumentCount, "unmatched par")?;
+            }
+        } else if self.gen.table.kind_t(t_index) == KindT::ProcId {  // パラメータのない手続きは () を省略できる
+            if self.gen.table.pars(t_index) != 0 {
+                self.lex.error(ErrorCode::ArgumentCount, "unmatched par")?;
+            }
+        } else {
+            self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Lparen).as_str())?;
+            self.lex.error(ErrorCode::MissingToken, format!("insert {:?}", KeyId::Rparen).as_str())?;
         }
+        self.gen.set_span(span);
+        let cal = self.gen.gen_code_t(OpCode::Cal, t_index)?;  // call命令
+        self.gen.table.add_forward_call(t_index, cal as usize);  // 前方宣言だけなら飛び先はあとでパッチ
+        Ok(())
+    }
+    fn store(&mut self, t_index: i32) -> Result<(), CompileError> {  // 変数への代入命令の生成
+        match self.gen.table.kind_t(t_index) {
+            KindT::VarId | KindT::ParId => { self.gen.gen_code_t(OpCode::Sto, t_index)?; },
+            KindT::VarParId => { self.gen.gen_code_t(OpCode::Sti, t_index)?; },  // varパラメータが指す変数へ
+            _ => {},                                          // 定数名などには番地がないので生成しない
+        }
+        Ok(())
+    }
+    fn var_arg(&mut self, ty: Type) -> Result<(), CompileError> {  // 型tyのvarパラメータの実引数のコンパイル（変数の番地を渡す）
+        let span = self.token.span;
+        let var = match self.token.u.clone() {                // 代入できる名前ならそのインデックス
+            TokenContent::Id(id) if self.token.kind == KeyId::Id => self.gen.table.search_t(&id)
+                .filter(|&t| is_variable(self.gen.table.kind_t(t))
+                        || matches!(self.gen.table.kind_t(t), KindT::ArrayId | KindT::ArrayParId)),
+            _ => None,
+        };
+        let t_index = match var {
+            Some(t_index) => t_index,
+            None => {
+                self.lex.error(ErrorCode::KindMismatch, "kind error: variable expected")?;
+                self.or_condition()?;                         // 式は読み飛ばさずにコンパイルしておく
+                return Ok(());
+            },
+        };
+        let k = self.gen.table.kind_t(t_index);
+        self.lex.set_id_kind(k);
+        self.check_type(span, ty, self.gen.table.type_t(t_index))?;  // 番地を渡すので型は同じでなければならない
+        if self.loop_vars.contains(&t_index) {                // for文の制御変数は書き換えられては困る
+            self.lex.error(ErrorCode::LoopVariableAssigned, "cannot pass loop variable by reference")?;
+        }
+        self.token = self.lex.next_token()?;
+        self.gen.set_span(span);
+        match k {
+            KindT::VarId | KindT::ParId => { self.gen.gen_code_t(OpCode::Lda, t_index)?; },  // 変数の番地
+            KindT::VarParId => { self.gen.gen_code_t(OpCode::Lod, t_index)?; },  // 受け取った番地をそのまま渡す
+            _ => {                                            // 配列の要素の番地
+                self.array_element(t_index)?;
+                self.gen.set_span(span);
+                self.gen.gen_code_v(OpCode::Idx, self.gen.table.array_size(t_index))?;
+            },
+        }
+        if !matches!(self.token.kind, KeyId::Comma | KeyId::Rparen) {  // 変数のあとに演算子などが続けば式なのでエラー
+            self.lex.error_at(span, ErrorCode::KindMismatch, "kind error: variable expected")?;
+            while !matches!(self.token.kind, KeyId::Comma | KeyId::Rparen | KeyId::Semicolon | KeyId::End | KeyId::Period) {
+                self.token = self.lex.next_token()?;          // 実引数の終わりまで読み飛ばす
+            }
+        }
+        Ok(())
+    }
+    fn array_size(&mut self) -> Result<i32, CompileError> {   // 配列の要素数「[」「定数」「]」のコンパイル（"[" を読んだところで呼ばれる）
+        self.token = self.lex.next_token()?;
+        let mut size = 1;                                     // エラーの場合は要素数1とする
+        let value = match self.token.u.clone() {
+            TokenContent::Value(v) if self.token.kind == KeyId::Num => Some(v),
+            TokenContent::Id(id) if self.token.kind == KeyId::Id => {  // 定数名でもよい
+                match self.gen.table.search_t(&id) {
+                    Some(t_index) if self.gen.table.kind_t(t_index) == KindT::ConstId => {
+                        self.lex.set_id_kind(KindT::ConstId);
+                        Some(self.gen.table.val(t_index))
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        };
+        match value {
+            Some(v) if v > 0 => {
+                size = v;
+                self.token = self.lex.next_token()?;
+            },
+            Some(_) => {
+                self.lex.error(ErrorCode::InvalidArraySize, "array size must be positive")?;
+                self.token = self.lex.next_token()?;
+            },
+            None => {
+                self.lex.error(ErrorCode::InvalidArraySize, "array size must be a constant")?;
+                if self.token.kind == KeyId::Id {
+                    self.token = self.lex.next_token()?;
+                }
+            },
+        }
+        let token = self.token.clone();
+        self.token = self.lex.check_get(token, KeyId::Rbracket)?;  // 最後は "]" のはず
+        Ok(size)
+    }
+    fn array_element(&mut self, t_index: i32) -> Result<(), CompileError> {  // 配列の先頭番地と添字を積むコードの生成（名前は読んだあと）
+        self.array_base(t_index)?;
+        let token = self.token.clone();
+        self.token = self.lex.check_get(token, KeyId::Lbracket)?;  // "[" のはず
+        self.typed_expression(Type::Integer)?;                // 添字の式のコンパイル
+        let token = self.token.clone();
+        self.token = self.lex.check_get(token, KeyId::Rbracket)?;  // "]" のはず
+        Ok(())
+    }
+    fn array_base(&mut self, t_index: i32) -> Result<(), CompileError> {  // 配列の先頭番地を積むコードの生成
+        match self.gen.table.kind_t(t_index) {
+            KindT::ArrayId => self.gen.gen_code_t(OpCode::Lda, t_index)?,     // 配列そのものの番地
+            KindT::ArrayParId => self.gen.gen_code_t(OpCode::Lod, t_index)?,  // パラメータには先頭番地が入っている
+            _ => unreachable!(),
+        };
+        Ok(())
+    }
+    fn array_arg(&mut self, size: i32, ty: Type) -> Result<(), CompileError> {  // 要素の型がtyの配列の実引数のコンパイル
+        let array = match self.token.u.clone() {              // 配列名ならそのインデックス
+            TokenContent::Id(id) if self.token.kind == KeyId::Id => self.gen.table.search_t(&id)
+                .filter(|&t| matches!(self.gen.table.kind_t(t), KindT::ArrayId | KindT::ArrayParId)),
+            _ => None,
+        };
+        match array {
+            Some(t_index) => {
+                self.lex.set_id_kind(self.gen.table.kind_t(t_index));
+                if self.gen.table.array_size(t_index) != size {  // 要素数が同じ配列のはず
+                    self.lex.error(ErrorCode::KindMismatch,
+                                   format!("kind error: array of size {} expected", size).as_str())?;
+                }
+                self.check_type(self.token.span, ty, self.gen.table.type_t(t_index))?;
+                self.gen.set_span(self.token.span);
+                self.array_base(t_index)?;                    // 先頭番地を渡す
+                self.token = self.lex.next_token()?;
+            },
+            None => {
+                self.lex.error(ErrorCode::KindMismatch, "kind error: array expected")?;
+                self.or_condition()?;                           // 式は読み飛ばさずにコンパイルしておく
+            },
+        }
+        Ok(())
+    }
+    fn search_id(&mut self, id: String, span: Span) -> Result<i32, CompileError> {  // 名前を名前表から探す。なければエラーにして変数として仮登録
+        if let Some(t_index) = self.gen.table.search_t(&id) {
+            return Ok(t_index);
+        }
+        let message = match self.gen.table.similar_name(&id) {
+            Some(name) => format!("undeclared identifier: {} (did you mean {}?)", id, name),
+            None => format!("undeclared identifier: {}", id),
+        };
+        self.lex.error_at(span, ErrorCode::UndeclaredIdentifier, message.as_str())?;
+        self.gen.table.enter_table_var(id, Type::Integer, span)  // 同じ名前でエラーが続かないように登録しておく
+    }
+    fn condition(&mut self) -> Result<(), CompileError> {     // 条件式のコンパイル
+        self.typed_expression(Type::Boolean)                  // 条件は真偽値の式
+    }
+    fn or_condition(&mut self) -> Result<Type, CompileError> {  // or でつながった式のコンパイル、式の型を返す
+        let left_span = self.token.span;                      // 左の式の位置
+        let ty = self.and_condition()?;
+        self.rest_of_or(left_span, ty)
+    }
+    fn rest_of_or(&mut self, mut left_span: Span, mut ty: Type) -> Result<Type, CompileError> {  // 最初のandの式を読んだあとの残り
+        while self.token.kind == KeyId::Or {
+            self.check_type(left_span, Type::Boolean, ty)?;   // 論理演算は真偽値どうし
+            let span = self.token.span;                       // 演算子の位置
+            self.token = self.lex.next_token()?;
+            self.gen.set_span(span);
+            let back_p = self.gen.gen_code_v(OpCode::Jpc, 0)?;   // 左が偽なら右を評価する
+            self.gen.gen_code_v(OpCode::Lit, 1)?;             // 左が真なら右を評価せずに真
+            let back_p2 = self.gen.gen_code_v(OpCode::Jmp, 0)?;
+            self.gen.back_patch(back_p as usize);
+            left_span = self.token.span;
+            let right = self.and_condition()?;
+            self.check_type(left_span, Type::Boolean, right)?;
+            self.gen.back_patch(back_p2 as usize);
+            ty = Type::Boolean;
+        }
+        Ok(ty)
+    }
+    fn and_condition(&mut self) -> Result<Type, CompileError> {  // and でつながった式のコンパイル
+        let left_span = self.token.span;                      // 左の式の位置
+        let ty = self.not_condition()?;
+        self.rest_of_and(left_span, ty)
+    }
+    fn rest_of_and(&mut self, mut left_span: Span, mut ty: Type) -> Result<Type, CompileError> {  // 最初のnotの式を読んだあとの残り
+        while self.token.kind == KeyId::And {
+            self.check_type(left_span, Type::Boolean, ty)?;   // 論理演算は真偽値どうし
+            let span = self.token.span;                       // 演算子の位置
+            self.token = self.lex.next_token()?;
+            self.gen.set_span(span);
+            let back_p = self.gen.gen_code_v(OpCode::Jpc, 0)?;   // 左が偽なら右を評価せずに偽
+            left_span = self.token.span;
+            let right = self.not_condition()?;
+            self.check_type(left_span, Type::Boolean, right)?;
+            let back_p2 = self.gen.gen_code_v(OpCode::Jmp, 0)?;
+            self.gen.back_patch(back_p as usize);
+            self.gen.set_span(span);
+            self.gen.gen_code_v(OpCode::Lit, 0)?;
+            self.gen.back_patch(back_p2 as usize);
+            ty = Type::Boolean;
+        }
+        Ok(ty)
+    }
+    fn not_condition(&mut self) -> Result<Type, CompileError> {  // not の付いた式のコンパイル
+        if self.token.kind == KeyId::Not {
+            let span = self.token.span;
+            self.token = self.lex.next_token()?;
+            let operand_span = self.token.span;
+            let ty = self.not_condition()?;
+            self.check_type(operand_span, Type::Boolean, ty)?;  // notは真偽値にだけ付けられる
+            self.gen.set_span(span);
+            self.gen.gen_code_v(OpCode::Lit, 0)?;             // 0と等しければ真
+            self.gen.gen_code_o(Operator::Eq)?;
+            return Ok(Type::Boolean);
+        }
+        self.relation()
+    }
+    fn relation(&mut self) -> Result<Type, CompileError> {    // 比較のコンパイル
+        if self.token.kind == KeyId::Odd {
+            let span = self.token.span;
+            self.token = self.lex.next_token()?;
+            let operand_span = self.token.span;
+            let ty = self.expression()?;
+            self.check_type(operand_span, Type::Integer, ty)?;  // oddは整数にだけ使える
+            self.gen.set_span(span);
+            self.gen.gen_code_o(Operator::Odd)?;
+            return Ok(Type::Boolean);
+        }
+        let left_span = self.token.span;                      // 左辺の位置
+        let ty = self.expression()?;
+        self.rest_of_relation(left_span, ty)
+    }
+    fn rest_of_relation(&mut self, left_span: Span, ty: Type) -> Result<Type, CompileError> {  // 左辺を読んだあとの比較の残り
+        let k = self.token.kind;
+        let span = self.token.span;                           // 関係演算子の位置
+        let optr = match k {
+            KeyId::Equal => Operator::Eq,
+            KeyId::Lss => Operator::Ls,
+            KeyId::Gtr => Operator::Gr,
+            KeyId::NotEq => Operator::Neq,
+            KeyId::LssEq => Operator::Lseq,
+            KeyId::GtrEq => Operator::Greq,
+            _ => return Ok(ty),                               // 関係演算子がなければ式だけ
+        };
+        self.token = self.lex.next_token()?;
+        let right_span = self.token.span;                     // 右辺の位置
+        let right = self.expression()?;
+        if k == KeyId::Equal || k == KeyId::NotEq {           // 等しいかどうかは同じ型どうしで比べる
+            self.check_type(right_span, ty, right)?;
+        } else {                                              // 大小は整数どうしで比べる
+            self.check_type(left_span, Type::Integer, ty)?;
+            self.check_type(right_span, Type::Integer, right)?;
+        }
+        self.gen.set_span(span);
+        self.gen.gen_code_o(optr)?;
+        Ok(Type::Boolean)
     }
     pub fn print_code(&self) {
         println!("\ninstructions for the virtual machine:");
         self.gen.print_code();
     }
-    pub fn execute(&self) {
-        println!("\nstart execution:");
-        self.gen.execute();
+    pub fn execute(&self) -> Result<(), VmError> {
+        self.gen.execute()
+    }
+    pub fn execute_with<R: std::io::BufRead, W: std::io::Write>(&self, input: &mut R, output: &mut W) -> Result<(), VmError> {
+        self.gen.execute_with(input, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_and_run(src: &str, input: &str) -> (Vec<Diagnostic>, Option<Result<String, VmError>>) {
+        // コンパイルして、エラーがなければ実行する。診断と出力（または実行時エラー）を返す
+        let mut lex = Lexer::new(src);
+        let mut table = NameTable::new();
+        let mut gen = CodeGenerator::new(&mut table);
+        let mut compiler = Compiler::new(&mut lex, &mut gen);
+        let diagnostics = match compiler.compile() {
+            Ok(diagnostics) => diagnostics,
+            Err(_) => compiler.diagnostics().to_vec(),  // 中断したときもそれまでの診断を返す
+        };
+        if error_count(&diagnostics) > 0 {
+            return (diagnostics, None);
+        }
+        let mut output = Vec::new();
+        let result = compiler.execute_with(&mut input.as_bytes(), &mut output)
+            .map(|_| String::from_utf8(output).unwrap());
+        (diagnostics, Some(result))
+    }
+
+    fn diagnostics(src: &str) -> Vec<Diagnostic> {  // コンパイルして、診断を返す
+        compile_and_run(src, "").0
+    }
+
+    fn run(src: &str) -> String {                 // エラーなく実行できたときの出力
+        run_input(src, "")
+    }
+
+    fn run_input(src: &str, input: &str) -> String {
+        match compile_and_run(src, input) {
+            (_, Some(Ok(output))) => output,
+            (diagnostics, result) => panic!("{:?} {:?}", diagnostics, result),
+        }
+    }
+
+    fn compile_result(src: &str) -> Result<Vec<Diagnostic>, CompileError> {
+        let mut lex = Lexer::new(src);
+        let mut table = NameTable::new();
+        let mut gen = CodeGenerator::new(&mut table);
+        Compiler::new(&mut lex, &mut gen).compile()
+    }
+
+    fn errors(src: &str) -> Vec<ErrorCode> {      // コンパイルエラーの種類
+        diagnostics(src).iter().filter(|d| d.is_error()).map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn reports_structured_diagnostics() {
+        let diagnostics = diagnostics("var count;\nbegin cuont := 1 end.");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, ErrorCode::UndeclaredIdentifier);
+        assert_eq!(diagnostics[0].message, "undeclared identifier: cuont (did you mean count?)");
+        assert_eq!(diagnostics[0].to_string(), format!("2:{}: error[E007]: {}",
+                   diagnostics[0].span.columns.start, diagnostics[0].message));
+        assert!(is_executable(&diagnostics));
+    }
+
+    #[test]
+    fn recovers_from_missing_tokens() {
+        assert_eq!(errors("var x; begin x := (1 + 2 end."), vec![ErrorCode::MissingToken]);
+        assert!(errors("var x; begin x := 1 end.").is_empty());
+        assert!(!is_executable(&diagnostics("begin x := ; y := ; z := end.")));
+    }
+
+    #[test]
+    fn fatal_errors_are_returned() {
+        assert_eq!(compile_result("var x; begin x := 1"), Err(CompileError::UnexpectedEof));
+        let names: Vec<String> = (0..40).map(|i| format!("a{} := 1", i)).collect();
+        let src = format!("begin {} end.", names.join("; "));
+        assert_eq!(compile_result(&src), Err(CompileError::TooManyErrors));
+        assert_eq!(diagnostics(&src).last().map(|d| d.code), Some(ErrorCode::Fatal));
+    }
+
+    #[test]
+    fn instructions_carry_source_spans() {
+        let mut lex = Lexer::new("var x;\nbegin x := 1 + 2 end.");
+        let mut table = NameTable::new();
+        let mut gen = CodeGenerator::new(&mut table);
+        Compiler::new(&mut lex, &mut gen).compile().unwrap();
+        let positions: Vec<(usize, usize)> = gen.code().iter()
+            .map(|inst| (inst.span().line, inst.span().column))
+            .collect();
+        assert_eq!(positions, vec![
+            (1, 1),                 // jmp: ブロックの先頭
+            (2, 1),                 // ict: begin
+            (2, 12),                // lit 1
+            (2, 16),                // lit 2
+            (2, 14),                // add: "+"
+            (2, 7),                 // sto: 代入文の左辺
+            (2, 21),                // ret: end
+        ]);
+    }
+
+    fn vm_error(src: &str, input: &str) -> VmError {  // 実行時エラー
+        match compile_and_run(src, input) {
+            (_, Some(Err(err))) => err,
+            (diagnostics, result) => panic!("{:?} {:?}", diagnostics, result),
+        }
+    }
+
+    #[test]
+    fn arithmetic_and_write() {
+        assert_eq!(run("begin write 1 + 2 * 3; write -7 / 2; writeln; write((1 - 4) * 2) end."), "7-3\n-6");
+        let src = "function fact(n) begin if n = 0 then return 1; return n * fact(n - 1) end;
+                   begin write fact(5) end.";
+        assert_eq!(run(src), "120");
+    }
+
+    #[test]
+    fn output_errors_stop_execution() {
+        struct Closed;                            // 書き込みに必ず失敗する出力先
+        impl std::io::Write for Closed {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut lex = Lexer::new("begin write 1 end.");
+        let mut table = NameTable::new();
+        let mut gen = CodeGenerator::new(&mut table);
+        let mut compiler = Compiler::new(&mut lex, &mut gen);
+        compiler.compile().unwrap();
+        assert_eq!(compiler.execute_with(&mut "".as_bytes(), &mut Closed),
+                   Err(VmError::Io(std::io::ErrorKind::BrokenPipe)));
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let err = vm_error("var x; begin x := 0; write 1 / x end.", "");
+        assert_eq!(err, VmError::DivisionByZero { pc: 6, depth: 0 });
+        assert_eq!(err.to_string(), "division by zero at pc 6 (call depth 0)");
+        assert!(matches!(vm_error("begin write 2147483647 + 1 end.", ""), VmError::ArithmeticOverflow { .. }));
+        assert!(matches!(vm_error("var x; begin x := -2147483647 - 1; write -x end.", ""),
+                         VmError::ArithmeticOverflow { .. }));
+        match vm_error("function f(n) begin return f(n + 1) end; begin write f(0) end.", "") {
+            VmError::StackOverflow { depth, .. } => assert!(depth > 0),
+            err => panic!("{:?}", err),
+        }
+    }
+
+    fn compile_with(src: &str, options: CompilerOptions) -> (Result<Vec<Diagnostic>, CompileError>, Vec<Diagnostic>) {
+        // 設定を指定してコンパイルし、結果と診断を返す
+        let mut lex = Lexer::new(src);
+        let mut table = NameTable::new();
+        let mut gen = CodeGenerator::new(&mut table);
+        let mut compiler = Compiler::with_options(&mut lex, &mut gen, options);
+        let result = compiler.compile();
+        (result, compiler.diagnostics().to_vec())
+    }
+
+    #[test]
+    fn code_size_limit() {
+        let writes = vec!["write 1"; 200].join("; ");
+        let src = format!("begin {} end.", writes);
+        assert!(compile_with(&src, CompilerOptions::default()).0.is_ok());   // 既定では制限なし
+        let (result, diagnostics) = compile_with(&src, CompilerOptions { max_code: Some(5), ..CompilerOptions::default() });
+        assert_eq!(result, Err(CompileError::TooManyCode { limit: 5 }));
+        let last = diagnostics.last().unwrap();
+        assert_eq!(last.code, ErrorCode::Fatal);
+        assert_eq!(last.message, "too many code (limit is 5 instructions)");
+    }
+
+    #[test]
+    fn name_table_and_nesting_limits() {
+        let mut src = String::from("var x;\n");              // 8重に入れ子になった関数
+        for i in 0..8 {
+            src += &format!("function f{}(n)\n", i);
+        }
+        src += "begin return x + n end;\n";
+        for i in (0..7).rev() {
+            src += &format!("begin return f{}(n + 1) end;\n", i + 1);
+        }
+        src += "begin x := 100; write f0(0) end.";
+        assert_eq!(run(&src), "107");                           // 既定では制限なし
+
+        let (result, diagnostics) = compile_with(&src, CompilerOptions { max_level: Some(3), ..CompilerOptions::default() });
+        assert_eq!(result, Err(CompileError::TooManyNestedBlocks { limit: 3 }));
+        assert_eq!(diagnostics.last().unwrap().message, "too many nested blocks (limit is 3 levels)");
+
+        let src = "var a, b, c, d; begin end.";
+        assert!(compile_with(src, CompilerOptions { max_names: Some(4), ..CompilerOptions::default() }).0.is_ok());
+        let (result, diagnostics) = compile_with(src, CompilerOptions { max_names: Some(3), ..CompilerOptions::default() });
+        assert_eq!(result, Err(CompileError::TooManyNames { limit: 3 }));
+        assert_eq!(diagnostics.last().unwrap().message, "too many names (limit is 3 names)");
+    }
+
+    #[test]
+    fn dangling_else_binds_to_innermost_if() {
+        let src = "function f(x, y)
+                   begin
+                     if x = 1 then if y = 1 then write 1 else write 2;
+                     return 0
+                   end;
+                   begin write f(1, 1); write f(1, 2); write f(2, 2) end.";
+        assert_eq!(run(src), "10200");               // elseは内側のifに対応する
+        assert_eq!(run("var x; begin x := 3; if x = 3 then else write 1; if x = 4 then write 2 else write 5 end."), "5");
+        assert_eq!(errors("begin else write 1 end."), vec![ErrorCode::ElseWithoutIf]);
+    }
+
+    #[test]
+    fn read_input() {
+        assert_eq!(run_input("var x, y; begin read x; read y; write x + y end.", "3\n 4"), "7");
+        assert_eq!(run_input("var x; begin read x; write -x end.", "-12\n"), "12");
+        assert!(matches!(vm_error("var x; begin read x end.", "abc"), VmError::InvalidInput { .. }));
+        assert!(matches!(vm_error("var x; begin read x end.", ""), VmError::UnexpectedEof { .. }));
+    }
+
+    #[test]
+    fn procedures_and_call() {
+        let src = "var x;
+                   procedure show(n) begin write n end;
+                   procedure p var x; begin x := 2; call show(x) end;
+                   begin x := 1; call p; show(x); p() end.";
+        assert_eq!(run(src), "212");
+        assert_eq!(errors("procedure p begin return 1 end; begin call p end."), vec![ErrorCode::ReturnMismatch]);
+        assert_eq!(errors("function f(n) begin return end; begin write f(1) end."), vec![ErrorCode::ReturnMismatch]);
+        assert_eq!(errors("var x; procedure p begin end; begin x := p() end."), vec![ErrorCode::KindMismatch]);
+    }
+
+    #[test]
+    fn arrays_and_array_parameters() {
+        let src = "const n = 3; var a[n], i;
+                   procedure fill(b[3]) var i; begin i := 0; while i < 3 do begin b[i] := i * 10; i := i + 1 end end;
+                   function sum(b[3]) begin return b[0] + b[1] + b[2] end;
+                   begin fill(a); a[2] := a[2] + 1; write a[1]; write sum(a) end.";
+        assert_eq!(run(src), "1031");
+        assert!(matches!(vm_error("var a[3], i; begin i := 3; a[i] := 1 end.", ""),
+                         VmError::IndexOutOfBounds { index: 3, size: 3, .. }));
+        assert!(matches!(vm_error("var a[3]; begin write a[-1] end.", ""),
+                         VmError::IndexOutOfBounds { index: -1, size: 3, .. }));
+        assert_eq!(errors("var a[0]; begin end."), vec![ErrorCode::InvalidArraySize]);
+        assert_eq!(errors("const c = 1; begin c := 2; read c end."), vec![ErrorCode::KindMismatch, ErrorCode::KindMismatch]);
+    }
+
+    #[test]
+    fn invalid_array_address() {                // 配列でない実引数は、エラーが少なければそのまま実行される
+        for arg in ["-1", "2147483647"] {
+            let src = format!("procedure p(a[2]) begin a[1] := 1 end; begin p({}) end.", arg);
+            let mut lex = Lexer::new(&src);
+            let mut table = NameTable::new();
+            let mut gen = CodeGenerator::new(&mut table);
+            let mut compiler = Compiler::new(&mut lex, &mut gen);
+            let diagnostics = compiler.compile().unwrap();
+            assert!(is_executable(&diagnostics));
+            let err = compiler.execute_with(&mut "".as_bytes(), &mut Vec::new()).unwrap_err();
+            assert!(matches!(err, VmError::InvalidAddress { depth: 1, .. }));
+            assert!(err.to_string().starts_with("invalid address at pc"));
+        }
+    }
+
+    #[test]
+    fn short_circuit_evaluation() {
+        let src = "function f(n): boolean begin write n; return true end;
+                   var i;
+                   begin
+                     i := 0;
+                     if i = 1 and f(1) then write 0;
+                     if i = 0 or f(2) then write 3;
+                     if i = 0 and f(4) then write 5;
+                     if not (i = 1 or f(6)) then write 7
+                   end.";
+        assert_eq!(run(src), "3456");
+    }
+
+    #[test]
+    fn remainder_operator() {
+        assert_eq!(run("begin write 7 mod 3; write -7 mod 3; write 7 % (-3); write 2 * 7 mod 4 end."), "1-112");
+        assert!(matches!(vm_error("var x; begin x := 0; write 1 mod x end.", ""), VmError::DivisionByZero { .. }));
+        assert_eq!(run("var x; begin x := -1; write((-2147483647 - 1) mod x) end."), "0");
+    }
+
+    #[test]
+    fn for_loops() {
+        let src = "var i, n;
+                   begin
+                     n := 3;
+                     for i := 1 to n do begin n := n + 1; write i end;
+                     for i := 3 downto 1 do write i;
+                     for i := 1 to 0 do write 99;
+                     write n
+                   end.";
+        assert_eq!(run(src), "1233216");           // 初期値と終値は一度だけ計算する
+        assert_eq!(errors("var i; begin for i := 1 to 2 do i := 0 end."), vec![ErrorCode::LoopVariableAssigned]);
+    }
+
+    #[test]
+    fn loops_with_break_and_continue() {
+        let src = "var i, n;
+                   begin
+                     for i := 1 to 10 do begin
+                       if odd i then continue;
+                       if i > 6 then break;
+                       write i
+                     end;
+                     n := 0;
+                     repeat n := n + 1; if n = 2 then continue; write n until n >= 4;
+                     while 1 = 1 do begin n := n - 1; if n = 0 then break end;
+                     write n
+                   end.";
+        assert_eq!(run(src), "2461340");
+        assert_eq!(errors("begin break end."), vec![ErrorCode::OutsideLoop]);
+        assert_eq!(errors("procedure p begin continue end; begin while 1 = 1 do call p end."),
+                   vec![ErrorCode::OutsideLoop]);
+    }
+
+    #[test]
+    fn missing_until_or_end_stops_at_period() {   // ピリオドで止まらないと字句解析が先に進まない
+        assert_eq!(errors("var x; begin repeat x := x + 1 end."), vec![ErrorCode::MissingToken]);
+        assert_eq!(errors("var x; begin repeat x := x + 1."), vec![ErrorCode::MissingToken, ErrorCode::MissingToken]);
+        assert_eq!(errors("var x; begin x := 1."), vec![ErrorCode::MissingToken]);
+    }
+
+    #[test]
+    fn constant_folding() {
+        assert_eq!(run("const a = -5, b = a * 2 + 1, c = (b - a) mod 4; begin write a; write b; write c end."), "-5-90");
+        assert_eq!(errors("const a = 2147483647 + 1, b = 1 / 0; begin end."),
+                   vec![ErrorCode::ConstantEvaluation, ErrorCode::ConstantEvaluation]);
+        assert_eq!(errors("begin write 2147483648 end."), vec![ErrorCode::NumberTooLarge]);
+    }
+
+    #[test]
+    fn var_parameters() {
+        let src = "var x, a[3];
+                   procedure swap(var p, var q) var t; begin t := p; p := q; q := t end;
+                   procedure inc(var v) begin v := v + 1 end;
+                   procedure twice(var v) begin inc(v); inc(v) end;
+                   begin
+                     x := 5; a[2] := 20;
+                     swap(x, a[2]);
+                     twice(x); read a[0];
+                     write x; write a[2]; write a[0]
+                   end.";
+        assert_eq!(run_input(src, "7"), "2257");
+        assert_eq!(errors("procedure p(var v) begin end; begin p(1 + 2) end."), vec![ErrorCode::KindMismatch]);
+        assert_eq!(errors("var i; procedure p(var v) begin end; begin for i := 1 to 2 do p(i) end."),
+                   vec![ErrorCode::LoopVariableAssigned]);
+    }
+
+    #[test]
+    fn type_errors() {
+        assert_eq!(errors("var a, b; begin write (a < b) end."), vec![ErrorCode::TypeMismatch]);
+        assert_eq!(errors("var x; begin if x then write 1 end."), vec![ErrorCode::TypeMismatch]);
+        assert_eq!(errors("var d: boolean; begin d := 1 end."), vec![ErrorCode::TypeMismatch]);
+        assert_eq!(errors("var d: string; begin end."), vec![ErrorCode::ExpectedType]);
+        assert_eq!(run("var d: boolean; begin d := 1 < 2; if d then write 1 end."), "1");
+        let diagnostics = diagnostics("var b: boolean; begin b := 2 + b end.");
+        assert_eq!(diagnostics[0].message, "type error: expected integer, found boolean");
+    }
+
+    #[test]
+    fn write_strings() {
+        assert_eq!(run(r#"begin write "a\"b\\"; write "x\ty\n"; write "" end."#), "a\"b\\x\ty\n");
+        assert_eq!(errors("begin write \"abc\nend."), vec![ErrorCode::UnterminatedString]);
+        assert_eq!(errors("begin write \"abc end."), vec![ErrorCode::UnterminatedString, ErrorCode::Fatal]);  // 最後の行
+        assert_eq!(errors(r#"begin write "a\qb" end."#), vec![ErrorCode::InvalidEscape]);
+    }
+
+    #[test]
+    fn write_with_lists_and_widths() {
+        assert_eq!(run("begin write(1 + 2 * 3, -7 / 2, -7 mod 3, 7 % (-3)); writeln end."), "7-3-11\n");
+        let src = "const w = 5; var b: boolean; begin writeln(\"a\\\"b\\\\\":w, 42:5, \"|\", 7:0); writeln; write(1) end.";
+        assert_eq!(run(src), " a\"b\\   42|7\n\n1");
+        assert_eq!(errors("begin write(1:-1) end."), vec![ErrorCode::InvalidFieldWidth]);
+        assert_eq!(errors("begin write(1:70000, \"x\":70000) end."),
+                   vec![ErrorCode::InvalidFieldWidth, ErrorCode::InvalidFieldWidth]);
+        assert_eq!(errors("var b: boolean; begin write(1, b) end."), vec![ErrorCode::TypeMismatch]);
+    }
+
+    #[test]
+    fn write_parenthesized_expression() {       // ")" のあとに演算子が続けば、式の続きとして読む
+        assert_eq!(run("var a; begin a := 2; write (a + 1) * 2; write (a) - 1:3; writeln (a) end."), "6  12\n");
+        assert!(matches!(vm_error("begin write (-2147483647 - 1) / (-1) end.", ""), VmError::ArithmeticOverflow { .. }));
+        let diagnostics = diagnostics("begin write (1) < 2 end.");
+        assert_eq!(diagnostics.iter().map(|d| d.code).collect::<Vec<_>>(), vec![ErrorCode::TypeMismatch]);
+    }
+
+    #[test]
+    fn forward_declarations() {
+        let src = "function odd2(n): boolean; forward;
+                   function even2(n): boolean begin if n = 0 then return true; return odd2(n - 1) end;
+                   function odd2(n): boolean begin if n = 0 then return false; return even2(n - 1) end;
+                   begin if odd2(7) and even2(10) then write 1 end.";
+        assert_eq!(run(src), "1");
+        assert_eq!(errors("function f(n); forward; function f(n, m) begin return n end; begin end."),
+                   vec![ErrorCode::ForwardMismatch]);
+        assert_eq!(errors("procedure p; forward; begin call p end."), vec![ErrorCode::UnresolvedForward]);
+        assert_eq!(errors("function f(n) forward; function f(n) begin return n end; begin end."),
+                   vec![ErrorCode::MissingToken]);
+    }
+
+    #[test]
+    fn name_resolution() {
+        let src = "var x; procedure p var x; begin x := 2; write x end; begin x := 1; call p; write x end.";
+        assert_eq!(run(src), "21");
+        let messages: Vec<String> = diagnostics("var y, count; begin z := 3; cuont := 1 end.").into_iter()
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(messages, vec!["undeclared identifier: z",                  // 1文字の名前には候補を出さない
+                                  "undeclared identifier: cuont (did you mean count?)"]);
+    }
+
+    #[test]
+    fn return_at_end_of_block() {               // 最後の文のreturnのあとにもret命令が要る
+        let src = "procedure p(x) begin write x; if x = 0 then return end;
+                   procedure q begin write 999 end;
+                   function f(n) begin if n > 0 then return 1 else return 2 end;
+                   begin call p(1); call p(0); write(f(0), f(1)) end.";
+        assert_eq!(run(src), "1021");
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let diagnostics = diagnostics("var a[2000000000], b[2000000000]; begin end.");
+        assert_eq!(diagnostics.last().map(|d| d.message.as_str()), Some("frame too large"));
+    }
+
+    #[test]
+    fn missing_factor() {
+        assert_eq!(errors("begin write(7 mod -3) end."), vec![ErrorCode::ExpectedNumber]);
     }
 }
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {                   // 診断の重大度
+    Error, Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorCode {                  // 診断の種類
+    MissingToken,                     // 記号や予約語の挿入
+    UnexpectedToken,                  // 記号や予約語の置き換え、読み捨て
+    ExpectedIdentifier,               // 名前がない
+    ExpectedNumber,                   // 数がない
+    NameTooLong,                      // 名前が長すぎる
+    NumberTooLarge,                   // 数が大きすぎる
+    UndeclaredIdentifier,             // 宣言されていない名前
+    KindMismatch,                     // 名前の種類が違う
+    ArgumentCount,                    // 実引数の個数が合わない
+    MissingOperator,                  // 演算子がない
+    UnterminatedComment,              // 注釈が閉じていない
+    ElseWithoutIf,                    // 対応するifのないelse
+    ReturnMismatch,                   // return文の値の有無が関数・手続きと合わない
+    InvalidArraySize,                 // 配列の要素数が正の定数でない
+    LoopVariableAssigned,             // for文の制御変数への代入
+    OutsideLoop,                      // 繰り返しの外のbreak・continue
+    ConstantEvaluation,               // 定数式の計算であふれや0による除算が起きた
+    ExpectedType,                     // 型名がない
+    TypeMismatch,                     // 式の型が違う
+    UnterminatedString,               // 文字列が閉じていない
+    InvalidEscape,                    // 文字列の中の知らないエスケープ
+    InvalidFieldWidth,                // 出力の桁数が負
+    ForwardMismatch,                  // 関数・手続きの定義が前方宣言と合わない
+    UnresolvedForward,                // 前方宣言した関数・手続きが定義されていない
+    Fatal,                            // コンパイルを続けられないエラー
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ErrorCode::MissingToken         => "E001",
+            ErrorCode::UnexpectedToken      => "E002",
+            ErrorCode::ExpectedIdentifier   => "E003",
+            ErrorCode::ExpectedNumber       => "E004",
+            ErrorCode::NameTooLong          => "E005",
+            ErrorCode::NumberTooLarge       => "E006",
+            ErrorCode::UndeclaredIdentifier => "E007",
+            ErrorCode::KindMismatch         => "E008",
+            // E009（関係演算子がない）は型検査に置き換えたので欠番にしておく
+            ErrorCode::ArgumentCount        => "E010",
+            ErrorCode::MissingOperator      => "E011",
+            ErrorCode::UnterminatedComment  => "E012",
+            ErrorCode::ElseWithoutIf        => "E013",
+            ErrorCode::ReturnMismatch       => "E014",
+            ErrorCode::InvalidArraySize     => "E015",
+            ErrorCode::LoopVariableAssigned => "E016",
+            ErrorCode::OutsideLoop          => "E017",
+            ErrorCode::ConstantEvaluation   => "E018",
+            ErrorCode::ExpectedType         => "E019",
+            ErrorCode::TypeMismatch         => "E020",
+            ErrorCode::UnterminatedString   => "E021",
+            ErrorCode::InvalidEscape        => "E022",
+            ErrorCode::InvalidFieldWidth    => "E023",
+            ErrorCode::ForwardMismatch      => "E024",
+            ErrorCode::UnresolvedForward    => "E025",
+            ErrorCode::Fatal                => "E999",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceSpan {               // 診断の位置
+    pub file: Option<String>,         // ソースファイル名
+    pub line: usize,                  // 行番号（1から）
+    pub columns: std::ops::Range<usize>,  // 桁の範囲（1から、終わりは含まない）
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {               // コンパイル時の診断
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: SourceSpan,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {    // 「ファイル名:行:桁: error[E001]: メッセージ」の形式
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.span.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}: {}[{}]: {}", self.span.line, self.span.columns.start,
+               self.severity.as_str(), self.code.as_str(), self.message)
+    }
+}
+
+pub fn error_count(diagnostics: &[Diagnostic]) -> usize {  // エラーの個数を返す
+    diagnostics.iter().filter(|d| d.is_error()).count()
+}
+
+pub fn render<W: Write>(out: &mut W, diagnostic: &Diagnostic, source: &str) -> io::Result<()> {
+    writeln!(out, "{}", diagnostic)?;                     // 診断の見出し
+    if let Some(line) = source.lines().nth(diagnostic.span.line.wrapping_sub(1)) {
+        let start = diagnostic.span.columns.start.max(1);
+        let width = diagnostic.span.columns.end.saturating_sub(start).max(1);
+        let indent: String = line.chars().take(start - 1)  // タブはそのまま残して桁を揃える
+            .map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        writeln!(out, "{}", line)?;                       // 該当する行と、その位置を示す ^^^
+        writeln!(out, "{}{}", indent, "^".repeat(width))?;
+    }
+    Ok(())
+}
+
+pub fn render_all<W: Write>(out: &mut W, diagnostics: &[Diagnostic], source: &str) -> io::Result<()> {
+    for diagnostic in diagnostics {
+        render(out, diagnostic, source)?;
+    }
+    match error_count(diagnostics) {                      // エラーの個数の出力
+        0 => {},
+        1 => writeln!(out, "1 error occur")?,
+        n => writeln!(out, "{} errors occur", n)?,
+    }
+    Ok(())
+}
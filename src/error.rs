@@ -0,0 +1,100 @@
+use std::fmt;
+use std::io;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompileError {               // コンパイルを続けられないエラー
+    UnexpectedEof,                    // プログラムの途中でファイルが終わった
+    TooManyErrors,                    // エラーが多すぎる
+    TooManyNames { limit: usize },    // 名前表に入りきらない
+    TooManyNestedBlocks { limit: usize },  // ブロックが深すぎる
+    TooManyCode { limit: usize },     // 目的コードが長すぎる
+    FrameTooLarge,                    // ブロックの変数の番地がi32に収まらない
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match *self {
+            CompileError::UnexpectedEof       => "end of file",
+            CompileError::TooManyErrors       => "too many errors",
+            CompileError::FrameTooLarge       => "frame too large",
+            CompileError::TooManyNames { limit } => {
+                return write!(f, "too many names (limit is {} names)", limit);
+            },
+            CompileError::TooManyNestedBlocks { limit } => {
+                return write!(f, "too many nested blocks (limit is {} levels)", limit);
+            },
+            CompileError::TooManyCode { limit } => {
+                return write!(f, "too many code (limit is {} instructions)", limit);
+            },
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VmError {                    // 仮想機械の実行時エラー（pcは命令語のアドレス、depthは関数呼び出しの深さ）
+    DivisionByZero { pc: usize, depth: usize },      // 0による除算
+    ArithmeticOverflow { pc: usize, depth: usize },  // 演算結果がi32に収まらない
+    StackOverflow { pc: usize, depth: usize },       // 実行時スタックがあふれた
+    StackUnderflow { pc: usize, depth: usize },      // 実行時スタックが空なのに値を降ろそうとした
+    InvalidJump { pc: usize, depth: usize },         // 目的コードの範囲外への飛び越し
+    InvalidInput { pc: usize, depth: usize },        // 入力が整数ではない
+    UnexpectedEof { pc: usize, depth: usize },       // 入力が終わっている
+    IndexOutOfBounds { index: i32, size: i32, pc: usize, depth: usize },  // 配列の添字が範囲外
+    InvalidAddress { pc: usize, depth: usize },      // 番地として使った値が実行時スタックの範囲外
+    Io(io::ErrorKind),                // 入出力のエラー
+}
+
+impl VmError {
+    pub fn pc(&self) -> Option<usize> {  // エラーが起きた命令語のアドレス
+        match *self {
+            VmError::DivisionByZero { pc, .. } | VmError::ArithmeticOverflow { pc, .. }
+            | VmError::StackOverflow { pc, .. } | VmError::StackUnderflow { pc, .. }
+            | VmError::InvalidJump { pc, .. } | VmError::InvalidInput { pc, .. }
+            | VmError::UnexpectedEof { pc, .. } | VmError::IndexOutOfBounds { pc, .. }
+            | VmError::InvalidAddress { pc, .. } => Some(pc),
+            VmError::Io(_) => None,
+        }
+    }
+    pub fn depth(&self) -> Option<usize> {  // エラーが起きたときの関数呼び出しの深さ
+        match *self {
+            VmError::DivisionByZero { depth, .. } | VmError::ArithmeticOverflow { depth, .. }
+            | VmError::StackOverflow { depth, .. } | VmError::StackUnderflow { depth, .. }
+            | VmError::InvalidJump { depth, .. } | VmError::InvalidInput { depth, .. }
+            | VmError::UnexpectedEof { depth, .. } | VmError::IndexOutOfBounds { depth, .. }
+            | VmError::InvalidAddress { depth, .. } => Some(depth),
+            VmError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match *self {
+            VmError::DivisionByZero { .. }     => "division by zero",
+            VmError::ArithmeticOverflow { .. } => "arithmetic overflow",
+            VmError::StackOverflow { .. }      => "stack overflow",
+            VmError::StackUnderflow { .. }     => "stack underflow",
+            VmError::InvalidJump { .. }        => "invalid jump",
+            VmError::InvalidInput { .. }       => "invalid integer input",
+            VmError::UnexpectedEof { .. }      => "unexpected end of input",
+            VmError::InvalidAddress { .. }     => "invalid address",
+            VmError::IndexOutOfBounds { index, size, pc, depth } => {
+                return write!(f, "index {} out of bounds for array of size {} at pc {} (call depth {})",
+                              index, size, pc, depth);
+            },
+            VmError::Io(kind) => return write!(f, "I/O error: {}", io::Error::from(kind)),
+        };
+        write!(f, "{} at pc {} (call depth {})", message, self.pc().unwrap(), self.depth().unwrap())
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<io::Error> for VmError {
+    fn from(err: io::Error) -> Self {
+        VmError::Io(err.kind())
+    }
+}
@@ -1,31 +1,38 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::fs::File;
 use std::collections::HashMap;
 
 use lazy_static::lazy_static;
 
 use super::table::KindT;
+use super::diagnostic::{Diagnostic, ErrorCode, Severity, SourceSpan};
+use super::error::CompileError;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum KeyId {                   // キーや文字の種類（名前）
     Begin, End,                    // 予約語の名前
-    If, Then,
+    If, Then, Else,
     While, Do,
-    Ret, Func,
+    For, To, Downto,
+    Repeat, Until, Break, Continue,
+    Integer, Boolean, True, False,
+    Ret, Func, Proc, Call, Forward,
     Var, Const, Odd,
-    Write, WriteLn,
+    And, Or, Not, Mod,
+    Write, WriteLn, Read,
     EndOfKeyWords,                 // 予約語の名前はここまで
     Plus, Minus,                   // 演算子と区切り記号の名前
-    Mult, Div,
+    Mult, Div, Percent,
     Lparen, Rparen,
+    Lbracket, Rbracket,
     Equal, Lss, Gtr,
     NotEq, LssEq, GtrEq,
     Comma, Period, Semicolon,
-    Assign,
+    Colon, Assign,
     EndOfKeySymbol,                // 演算子と区切り記号の名前はここまで
-    Id, Num, Nul,                  // トークンの種類
+    Id, Num, Str, Nul,             // トークンの種類
     EndOfToken,
-    Letter, Digit, Colon, Other,   // 上記以外の文字の種類
+    Letter, Digit, Quote, Other,   // 上記以外の文字の種類
 }
 
 impl KeyId {
@@ -45,22 +52,45 @@ lazy_static! {
             ("end",     KeyId::End),
             ("if",      KeyId::If),
             ("then",    KeyId::Then),
+            ("else",    KeyId::Else),
             ("while",   KeyId::While),
             ("do",      KeyId::Do),
+            ("for",     KeyId::For),
+            ("to",      KeyId::To),
+            ("downto",  KeyId::Downto),
+            ("repeat",  KeyId::Repeat),
+            ("until",   KeyId::Until),
+            ("break",   KeyId::Break),
+            ("continue",KeyId::Continue),
+            ("integer", KeyId::Integer),
+            ("boolean", KeyId::Boolean),
+            ("true",    KeyId::True),
+            ("false",   KeyId::False),
             ("return",  KeyId::Ret),
             ("function",KeyId::Func),
+            ("procedure",KeyId::Proc),
+            ("call",    KeyId::Call),
+            ("forward", KeyId::Forward),
             ("var",     KeyId::Var),
             ("const",   KeyId::Const),
             ("odd",     KeyId::Odd),
+            ("and",     KeyId::And),
+            ("or",      KeyId::Or),
+            ("not",     KeyId::Not),
+            ("mod",     KeyId::Mod),
             ("write",   KeyId::Write),
             ("writeln", KeyId::WriteLn),
+            ("read",    KeyId::Read),
             ("$dummy1", KeyId::EndOfKeyWords),        // 記号と名前（KeyId）の表
             ("+",       KeyId::Plus),
             ("-",       KeyId::Minus),
             ("*",       KeyId::Mult),
             ("/",       KeyId::Div),
+            ("%",       KeyId::Percent),
             ("(",       KeyId::Lparen),
             (")",       KeyId::Rparen),
+            ("[",       KeyId::Lbracket),
+            ("]",       KeyId::Rbracket),
             ("=",       KeyId::Equal),
             ("<",       KeyId::Lss),
             (">",       KeyId::Gtr),
@@ -70,6 +100,7 @@ lazy_static! {
             (",",       KeyId::Comma),
             (".",       KeyId::Period),
             (";",       KeyId::Semicolon),
+            (":",       KeyId::Colon),
             (":=",      KeyId::Assign),
             ("$dummy2", KeyId::EndOfKeySymbol),
         ].iter().cloned().collect();
@@ -90,8 +121,11 @@ lazy_static! {
         table.insert('-', KeyId::Minus);
         table.insert('*', KeyId::Mult);
         table.insert('/', KeyId::Div);
+        table.insert('%', KeyId::Percent);
         table.insert('(', KeyId::Lparen);
         table.insert(')', KeyId::Rparen);
+        table.insert('[', KeyId::Lbracket);
+        table.insert(']', KeyId::Rbracket);
         table.insert('=', KeyId::Equal);
         table.insert('<', KeyId::Lss);
         table.insert('>', KeyId::Gtr);
@@ -99,6 +133,7 @@ lazy_static! {
         table.insert('.', KeyId::Period);
         table.insert(';', KeyId::Semicolon);
         table.insert(':', KeyId::Colon);
+        table.insert('"', KeyId::Quote);
         table
     };
 }
@@ -113,29 +148,50 @@ const MAXNUM: usize = 14;          // 定数の最大行数
 pub enum TokenContent {         // Tokenのunionに相当する型
     Id(String),                 // Identifierの時、その名前
     Value(i32),                 // Numの時、その値
+    Str(String),                // Strの時、エスケープを解いた文字列
     Nothing,                    // 未初期化時
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {               // ソースプログラム上の位置
+    pub offset: usize,          // 先頭のバイト位置
+    pub len: usize,             // バイト数
+    pub line: usize,            // 行番号（1から）
+    pub column: usize,          // 先頭の桁（1から）
+}
+
 #[derive(Clone, Debug)]
 pub struct Token {              // トークンの型
     pub kind: KeyId,            // トークンの種類かキーの名前
     pub u: TokenContent,        // unionに対応する型
+    pub span: Span,             // トークンの位置
 }
 
 impl Token {
     pub fn is_st_begin_key(&self) -> bool {     // トークンは文の先頭のキーか？
-        match self.kind {
-            KeyId::If | KeyId::Begin | KeyId::Ret | KeyId::While | KeyId::Write | KeyId::WriteLn => {
-                true
-            },
-            _ => false
-        }
+        matches!(self.kind,
+            KeyId::If | KeyId::Begin | KeyId::Ret | KeyId::While | KeyId::Write | KeyId::WriteLn | KeyId::Read
+            | KeyId::Call | KeyId::For | KeyId::Repeat | KeyId::Break | KeyId::Continue)
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommentKind {          // 注釈の種類
+    Line,                       // "//" から行末まで
+    Brace,                      // "{" . . "}"
+    ParenStar,                  // "(*" . . "*)"
+}
+
+#[derive(Clone, Debug)]
+pub struct Comment {            // 注釈（トークン以外の情報として残しておく）
+    pub kind: CommentKind,
+    pub text: String,           // 区切り記号も含めた注釈の綴り
+    pub span: Span,             // 注釈の位置
+}
+
 pub struct Lexer<'a> {
     lines: std::str::Lines<'a>,           // 次の行を先頭から出力するイテレータ
-    line_chars: std::str::Chars<'a>,      // 現在の行の文字を先頭から出力するイテレータ
+    line_chars: std::str::CharIndices<'a>,  // 現在の行の文字を先頭から出力するイテレータ
     line_index: i32,             // 次に読む文字の位置
     ch: char,                    // 最後に読んだ文字
     c_token: Token,              // 最後に読んだトークン
@@ -144,56 +200,166 @@ pub struct Lexer<'a> {
     cr: i32,                     // その前のCRの数
     printed: i32,                // トークンは印字済みか
     error_no: i32,               // 出力したエラーの数
+    diagnostics: Vec<Diagnostic>,  // 出力した診断
+    file_name: Option<String>,   // 診断に付けるソースファイル名
+    source: &'a str,             // ソースプログラム全体
+    line: &'a str,               // 現在の行
+    line_no: usize,              // 読み込んだ行の数（現在の行の行番号）
+    ch_span: Span,               // 最後に読んだ文字の位置
+    prev_span: Span,             // その一つ前に読んだ文字の位置
+    token_span: Span,            // 最後に読んだ（読んでいる）トークンの位置
+    comments: Vec<Comment>,      // 読み飛ばした注釈
+    listing: Option<Box<dyn Write + 'a>>,  // ソースプログラムのリスティングの出力先（Noneなら出力しない）
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(program: &'a String) -> Lexer<'a> { // initSourceに相当。変数の初期設定
+    pub fn new(program: &'a str) -> Lexer<'a> { // initSourceに相当。変数の初期設定
         let lines = program.lines();
-        let line_chars = "".chars();
+        let line_chars = "".char_indices();
         let ch = '\n';
-        let c_token = Token { kind: KeyId::Nul, u: TokenContent::Nothing };
+        let c_token = Token { kind: KeyId::Nul, u: TokenContent::Nothing, span: Span::default() };
 
-        let token_a = Lexer {
+        Lexer {
             lines, line_chars, line_index: -1, ch,
             c_token, id_kind: KindT::VarId, spaces: 0, cr: 0, printed: 1,  // id_kindの初期値は適当（使用しない）
-            error_no: 0,
-        };
-        token_a
-    }
-    pub fn error(&mut self, message: &str) {       // 通常のエラーメッセージの出力
-        if self.line_index > 0 {
-            println!("{:>count$}", "***^", count=(self.line_index as usize));
-        } else {
-            println!("^");
+            error_no: 0, diagnostics: Vec::new(), file_name: None,
+            source: program, line: &program[..0], line_no: 0,  // 最初は空の行を読んだことにする
+            ch_span: Span::default(), prev_span: Span::default(), token_span: Span::default(),
+            comments: Vec::new(), listing: None,
         }
-        println!("*** error *** {}", message);
+    }
+    pub fn set_listing(&mut self, out: Box<dyn Write + 'a>) {  // 読んだ行をそのまま出力する先をセット
+        self.listing = Some(out);
+    }
+    pub fn set_file_name(&mut self, name: &str) {  // 診断に付けるソースファイル名をセット
+        self.file_name = Some(name.to_string());
+    }
+    fn report(&mut self, severity: Severity, code: ErrorCode, message: &str, span: Span) {
+        let width = self.source.get(span.offset..span.offset + span.len)
+            .map_or(0, |text| text.chars().count());
+        self.diagnostics.push(Diagnostic {
+            severity, code,
+            message: message.to_string(),
+            span: SourceSpan {
+                file: self.file_name.clone(),
+                line: span.line,
+                columns: span.column..span.column + width.max(1),
+            },
+        });
+    }
+    pub fn error(&mut self, code: ErrorCode, message: &str) -> Result<(), CompileError> { // 通常のエラーの診断
+        self.error_at(self.token_span, code, message)  // 最後に読んだトークンの位置に対する診断
+    }
+    pub fn error_at(&mut self, span: Span, code: ErrorCode, message: &str) -> Result<(), CompileError> {
+        self.report(Severity::Error, code, message, span);
         self.error_no += 1;
         if self.error_no > MAX_ERROR {             // errorNoCheckの処理に相当
-            eprintln!("too many errors");
-            println!("abort compilation");
-            std::process::exit(1);
+            return Err(CompileError::TooManyErrors);
         }
+        Ok(())
+    }
+    pub fn fatal(&mut self, err: CompileError) {   // コンパイルを続けられないエラーの診断
+        self.report(Severity::Error, ErrorCode::Fatal, err.to_string().as_str(), self.token_span);
+        self.error_no += 1;
+    }
+    pub fn warning(&mut self, code: ErrorCode, message: &str) {  // 警告の診断
+        self.report(Severity::Warning, code, message, self.token_span);
+    }
+    pub fn warning_at(&mut self, span: Span, code: ErrorCode, message: &str) {  // 位置を指定した警告の診断
+        self.report(Severity::Warning, code, message, span);
     }
     pub fn error_n(&self) -> i32 {                 // エラーの個数を返す
         self.error_no
     }
-    fn next_char(&mut self) -> char {              // 次の１文字を返す関数
-        if let Some(ch) = self.line_chars.next() {
+    pub fn diagnostics(&self) -> &[Diagnostic] {   // これまでの診断を返す
+        &self.diagnostics
+    }
+    pub fn comments(&self) -> &[Comment] {         // これまでに読み飛ばした注釈を返す
+        &self.comments
+    }
+    fn next_char(&mut self) -> Result<char, CompileError> { // 次の１文字を返す関数
+        self.prev_span = self.ch_span;
+        if let Some((i, ch)) = self.line_chars.next() {
             self.line_index += 1;
-            ch
+            self.ch_span = Span {
+                offset: self.line_offset() + i, len: ch.len_utf8(),
+                line: self.line_no, column: (self.line_index as usize) + 1,
+            };
+            Ok(ch)
+        } else if let Some(line) = self.lines.next() {
+            if let Some(out) = self.listing.as_mut() {
+                let _ = writeln!(out, "{}", line);  // リスティングの出力の失敗はコンパイルに影響させない
+            }
+            self.ch_span = Span {                  // 改行は前の行の末尾にあるとみなす
+                offset: self.line_offset() + self.line.len(), len: 0,
+                line: self.line_no, column: self.line.chars().count() + 1,
+            };
+            self.line = line;
+            self.line_chars = line.char_indices();
+            self.line_index = -1;
+            self.line_no += 1;
+            Ok('\n')
+        } else {
+            Err(CompileError::UnexpectedEof)       // end of fileならコンパイル終了
+        }
+    }
+    fn peek_char(&self) -> Option<char> {          // 同じ行の次の１文字を読まずに返す
+        self.line_chars.clone().next().map(|(_, ch)| ch)
+    }
+    fn skip_comment(&mut self) -> Result<(), CompileError> {  // 注釈を読み飛ばす（self.chは注釈の先頭の文字）
+        let start = self.ch_span;
+        let kind = if self.ch == '/' {
+            CommentKind::Line
+        } else if self.ch == '{' {
+            CommentKind::Brace
         } else {
-            if let Some(line) = self.lines.next() {
-                println!("{}", line);
-                self.line_chars = line.chars();
-                self.line_index = -1;
-                '\n'
-            } else {
-                self.error("end of file");         // end of fileならコンパイル終了
-                std::process::exit(1);
+            CommentKind::ParenStar
+        };
+        if kind == CommentKind::Line {             // 行末まで読み飛ばす
+            while self.ch != '\n' {
+                self.ch = self.next_char()?;
+            }
+        } else if let Err(err) = self.skip_block_comment(kind) {
+            if err == CompileError::UnexpectedEof {  // 閉じていない注釈
+                self.error_at(start, ErrorCode::UnterminatedComment, "unterminated comment")?;
             }
+            return Err(err);
         }
+        let end = self.prev_span.offset + self.prev_span.len;
+        let text = self.source[start.offset..end].to_string();
+        self.comments.push(Comment { kind, text, span: Span { len: end - start.offset, ..start } });
+        Ok(())
     }
-    pub fn next_token(&mut self) -> Token {        // 次のトークンを読んで返す
+    fn skip_block_comment(&mut self, kind: CommentKind) -> Result<(), CompileError> {  // 入れ子になった注釈の終わりまで読み飛ばす
+        let mut depth = 0;                         // 同じ種類の注釈だけを入れ子として数える
+        loop {
+            match self.ch {
+                '{' if kind == CommentKind::Brace => {
+                    depth += 1;
+                },
+                '(' if kind == CommentKind::ParenStar && self.peek_char() == Some('*') => {
+                    depth += 1;
+                    self.ch = self.next_char()?;
+                },
+                '}' if kind == CommentKind::Brace => {
+                    depth -= 1;
+                },
+                '*' if kind == CommentKind::ParenStar && self.peek_char() == Some(')') => {
+                    depth -= 1;
+                    self.ch = self.next_char()?;
+                },
+                _ => (),
+            }
+            self.ch = self.next_char()?;
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+    fn line_offset(&self) -> usize {               // 現在の行の先頭のバイト位置
+        self.line.as_ptr() as usize - self.source.as_ptr() as usize
+    }
+    pub fn next_token(&mut self) -> Result<Token, CompileError> { // 次のトークンを読んで返す
         self.spaces = 0;
         self.cr = 0;
         loop {
@@ -204,12 +370,25 @@ impl<'a> Lexer<'a> {
                     self.spaces = 0;
                     self.cr += 1;
                 },
+                '{' => {                            // 注釈は読み飛ばす
+                    self.skip_comment()?;
+                    continue;
+                },
+                '(' if self.peek_char() == Some('*') => {
+                    self.skip_comment()?;
+                    continue;
+                },
+                '/' if self.peek_char() == Some('/') => {
+                    self.skip_comment()?;
+                    continue;
+                },
                 _    => break
             };
-            self.ch = self.next_char();
+            self.ch = self.next_char()?;
         }
+        self.token_span = self.ch_span;            // トークンの先頭の位置
 
-        let mut temp = Token { kind: KeyId::Nul, u: TokenContent::Nothing };
+        let mut temp = Token { kind: KeyId::Nul, u: TokenContent::Nothing, span: self.ch_span };
         let mut ident = String::new();
         let mut i = 0;
 
@@ -221,7 +400,7 @@ impl<'a> Lexer<'a> {
                             ident.push(self.ch);
                         }
                         i += 1;
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                         let next_cc = CHAR_CLASS_TABLE.get(&self.ch);
                         if let Some(next_cc) = next_cc {
                             *next_cc == KeyId::Letter || *next_cc == KeyId::Digit
@@ -229,24 +408,31 @@ impl<'a> Lexer<'a> {
                             false
                         }
                     } {}
+                    temp.span = self.end_token();
                     if i >= MAXNAME {
-                        self.error("too long");
+                        self.error(ErrorCode::NameTooLong, "too long")?;
                     }
                     if let Some(kind) = KEY_WORD_TABLE.get(&ident.as_str()) {  // 予約語の場合
                         temp.kind = *kind;
+                        temp.span = self.end_token();
                         self.c_token = temp.clone();
                         self.printed = 0;
-                        return temp;
+                        return Ok(temp);
                     }
                     temp.kind = KeyId::Id;
                     temp.u = TokenContent::Id(ident);
                 },
                 KeyId::Digit  => {                  // number
-                    let mut num = 0;
+                    let mut num: i32 = 0;
+                    let mut overflow = false;       // i32に収まらない
                     while {
-                        num = 10*num + (self.ch.to_digit(10).unwrap() as i32);
+                        let d = self.ch.to_digit(10).unwrap() as i32;
+                        match num.checked_mul(10).and_then(|n| n.checked_add(d)) {
+                            Some(n) => num = n,
+                            None => overflow = true,
+                        }
                         i += 1;
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                         let next_cc = CHAR_CLASS_TABLE.get(&self.ch);
                         if let Some(next_cc) = next_cc {
                             *next_cc == KeyId::Digit
@@ -254,37 +440,42 @@ impl<'a> Lexer<'a> {
                             false
                         }
                     } {}
-                    if i > MAXNUM {
-                        self.error("too large");
+                    temp.span = self.end_token();
+                    if i > MAXNUM || overflow {
+                        self.error(ErrorCode::NumberTooLarge, "too large")?;
                     }
                     temp.kind = KeyId::Num;
                     temp.u = TokenContent::Value(num);
                 },
+                KeyId::Quote  => {                  // string
+                    temp.kind = KeyId::Str;
+                    temp.u = TokenContent::Str(self.string_literal()?);
+                },
                 KeyId::Colon  => {
-                    self.ch = self.next_char();
+                    self.ch = self.next_char()?;
                     if self.ch == '=' {
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                         temp.kind = KeyId::Assign;  // ":="
                     } else {
-                        temp.kind = KeyId::Nul;
+                        temp.kind = KeyId::Colon;
                     }
                 },
                 KeyId::Lss    => {
-                    self.ch = self.next_char();
+                    self.ch = self.next_char()?;
                     if self.ch == '=' {
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                         temp.kind = KeyId::LssEq;   // "<="
                     } else if self.ch == '>' {
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                         temp.kind = KeyId::NotEq;   // "<>"
                     } else {
                         temp.kind = KeyId::Lss;
                     }
                 },
                 KeyId::Gtr    => {
-                    self.ch = self.next_char();
+                    self.ch = self.next_char()?;
                     if self.ch == '=' {
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                         temp.kind = KeyId::GtrEq;   // ">="
                     } else {
                         temp.kind = KeyId::Gtr;
@@ -294,22 +485,80 @@ impl<'a> Lexer<'a> {
                     temp.kind = *cc;
                     temp.u = TokenContent::Nothing;
                     if temp.kind != KeyId::Period {
-                        self.ch = self.next_char();
+                        self.ch = self.next_char()?;
                     }
                 }
             }
+            temp.span = self.end_token();
             self.c_token = temp.clone();
             self.printed = 0;
-            temp
+            Ok(temp)
         } else {
-            let temp = Token { kind: KeyId::Other, u: TokenContent::Nothing };
-            self.ch = self.next_char();
+            self.ch = self.next_char()?;
+            let temp = Token { kind: KeyId::Other, u: TokenContent::Nothing, span: self.end_token() };
             self.c_token = temp.clone();
             self.printed = 0;
-            temp
+            Ok(temp)
+        }
+    }
+    fn string_literal(&mut self) -> Result<String, CompileError> {  // 「"」から「"」までの文字列を読む（self.chは最初の「"」）
+        match self.string_chars() {
+            Err(CompileError::UnexpectedEof) => {   // 最後の行で閉じていない文字列
+                let span = self.end_token();
+                self.error_at(span, ErrorCode::UnterminatedString, "unterminated string")?;
+                Err(CompileError::UnexpectedEof)
+            },
+            result => result,
         }
     }
-    pub fn check_get(&mut self, t: Token, k: KeyId) -> Token {
+    fn string_chars(&mut self) -> Result<String, CompileError> {  // 文字列の中身を読み、閉じる「"」の次まで進む
+        let mut s = String::new();
+        self.ch = self.next_char()?;
+        loop {
+            match self.ch {
+                '"' => {
+                    self.ch = self.next_char()?;
+                    return Ok(s);
+                },
+                '\n' => {                         // 文字列は行をまたげない、改行は読まずに残す
+                    let span = self.end_token();
+                    self.error_at(span, ErrorCode::UnterminatedString, "unterminated string")?;
+                    return Ok(s);
+                },
+                '\\' => {                         // エスケープ：\" \\ \n \t
+                    let start = self.ch_span;
+                    self.ch = self.next_char()?;
+                    match self.ch {
+                        '"' => s.push('"'),
+                        '\\' => s.push('\\'),
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        '\n' => {                 // 行末の \ は閉じていない文字列とする
+                            continue;
+                        },
+                        c => {
+                            let span = Span { len: self.ch_span.offset + self.ch_span.len - start.offset, ..start };
+                            self.error_at(span, ErrorCode::InvalidEscape, format!("unknown escape sequence: \\{}", c).as_str())?;
+                            s.push(c);
+                        },
+                    }
+                },
+                c => s.push(c),
+            }
+            self.ch = self.next_char()?;
+        }
+    }
+    fn end_token(&mut self) -> Span {              // トークンの終わりを記録して、その位置を返す
+        let start = self.token_span;
+        let end = if self.ch_span == start {       // 文字を読み進めていない（"."）
+            start.offset + start.len
+        } else {
+            self.prev_span.offset + self.prev_span.len
+        };
+        self.token_span = Span { len: end - start.offset, ..start };
+        self.token_span
+    }
+    pub fn check_get(&mut self, t: Token, k: KeyId) -> Result<Token, CompileError> {
         /*
         t.kind == k なら、次のトークンを読んで返す
         t.kind != k ならエラーメッセージを出し、tとkが共に記号、または予約語なら
@@ -320,12 +569,12 @@ impl<'a> Lexer<'a> {
             return self.next_token();
         }
         if (k.is_key_word() && t.kind.is_key_word()) || (k.is_key_symbol() && t.kind.is_key_symbol()) {
-            self.error(format!("delete {:?}, and insert {:?}", self.c_token, k).as_str());
+            self.error(ErrorCode::UnexpectedToken, format!("delete {:?}, and insert {:?}", self.c_token.kind, k).as_str())?;
             self.printed = 1;
             return self.next_token();
         }
-        self.error(format!("insert {:?}", k).as_str());
-        t
+        self.error(ErrorCode::MissingToken, format!("insert {:?}", k).as_str())?;
+        Ok(t)
     }
     pub fn set_id_kind(&mut self, k: KindT) {     // 現トークン（Id）の種類をセット
         self.id_kind = k;
@@ -339,15 +588,128 @@ pub fn get_content(filename: String) -> io::Result<String> {
     Ok(contents)
 }
 
-pub fn open_source() -> io::Result<String> {       // ソースファイルのopen
+pub fn open_source() -> io::Result<(String, String)> {  // ソースファイルのopen（ファイル名と内容を返す）
     let filename = std::env::args().nth(1);
     if let Some(filename) = filename {
-        Ok(get_content(filename)?)
+        let content = get_content(filename.clone())?;
+        Ok((filename, content))
     } else {
         let mut filename = String::new();
         println!("enter source file name");
         io::stdin().read_line(&mut filename)?;
         filename = filename.trim_end().to_string();
-        Ok(get_content(filename)?)
+        let content = get_content(filename.clone())?;
+        Ok((filename, content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(src: &str) -> Vec<Token> {          // ソースプログラムをピリオドまで字句解析する
+        let mut lex = Lexer::new(src);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lex.next_token().unwrap();
+            let kind = token.kind;
+            tokens.push(token);
+            if kind == KeyId::Period {
+                return tokens;
+            }
+        }
+    }
+
+    #[test]
+    fn tokens_carry_their_spans() {
+        let tokens = tokens("var count;\n  count := 12.");
+        let spans: Vec<(usize, usize, usize, usize)> = tokens.iter()
+            .map(|t| (t.span.offset, t.span.len, t.span.line, t.span.column))
+            .collect();
+        assert_eq!(spans, vec![
+            (0, 3, 1, 1),           // var
+            (4, 5, 1, 5),           // count
+            (9, 1, 1, 10),          // ;
+            (13, 5, 2, 3),          // count
+            (19, 2, 2, 9),          // :=
+            (22, 2, 2, 12),         // 12
+            (24, 1, 2, 14),         // .
+        ]);
+    }
+
+    #[test]
+    fn comments_are_skipped_and_kept() {
+        let src = "var { a { nested } one } x; // to end of line\n(* b (* c *) *) x.";
+        let mut lex = Lexer::new(src);
+        let mut kinds = Vec::new();
+        loop {
+            let token = lex.next_token().unwrap();
+            kinds.push(token.kind);
+            if token.kind == KeyId::Period {
+                break;
+            }
+        }
+        assert_eq!(kinds, vec![KeyId::Var, KeyId::Id, KeyId::Semicolon, KeyId::Id, KeyId::Period]);
+        let comments: Vec<(CommentKind, &str, usize, usize)> = lex.comments().iter()
+            .map(|c| (c.kind, c.text.as_str(), c.span.line, c.span.column))
+            .collect();
+        assert_eq!(comments, vec![
+            (CommentKind::Brace, "{ a { nested } one }", 1, 5),
+            (CommentKind::Line, "// to end of line", 1, 29),
+            (CommentKind::ParenStar, "(* b (* c *) *)", 2, 1),
+        ]);
+        assert!(lex.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn other_kind_of_opener_does_not_nest() {
+        for src in ["(* a { brace *) x.", "{ f(*p) } x."] {
+            let mut lex = Lexer::new(src);
+            assert_eq!(lex.next_token().unwrap().kind, KeyId::Id);
+            assert_eq!(lex.next_token().unwrap().kind, KeyId::Period);
+            assert_eq!(lex.comments().len(), 1);
+            assert!(lex.diagnostics().is_empty());
+        }
+    }
+
+    #[test]
+    fn unterminated_comment_is_reported() {
+        let mut lex = Lexer::new("var x; { never closed\nbegin end.");
+        let result = loop {
+            match lex.next_token() {
+                Ok(token) if token.kind == KeyId::Period => break Ok(()),
+                Ok(_) => (),
+                Err(err) => break Err(err),
+            }
+        };
+        assert_eq!(result, Err(CompileError::UnexpectedEof));
+        let diagnostic = &lex.diagnostics()[0];
+        assert_eq!(diagnostic.code, ErrorCode::UnterminatedComment);
+        assert_eq!((diagnostic.span.line, diagnostic.span.columns.start), (1, 8));
+    }
+
+    #[test]
+    fn listing_goes_to_the_given_sink() {
+        let mut listing: Vec<u8> = Vec::new();
+        {
+            let mut lex = Lexer::new("var x;\nbegin x := 1 end.");
+            lex.set_listing(Box::new(&mut listing));
+            while lex.next_token().unwrap().kind != KeyId::Period {}
+        }
+        assert_eq!(String::from_utf8(listing).unwrap(), "var x;\nbegin x := 1 end.\n");
+    }
+
+    #[test]
+    fn check_get_replaces_a_token_of_the_same_class() {
+        let mut lex = Lexer::new("while x < 1 then x := 1.");
+        let mut token = lex.next_token().unwrap();
+        while token.kind != KeyId::Then {
+            token = lex.next_token().unwrap();
+        }
+        let next = lex.check_get(token, KeyId::Do).unwrap();
+        assert_eq!(next.kind, KeyId::Id);
+        let diagnostic = &lex.diagnostics()[0];
+        assert_eq!(diagnostic.code, ErrorCode::UnexpectedToken);
+        assert_eq!(diagnostic.message, "delete Then, and insert Do");
     }
 }
@@ -2,3 +2,5 @@ pub mod get_source;
 pub mod table;
 pub mod codegen;
 pub mod compile;
+pub mod diagnostic;
+pub mod error;
@@ -2,30 +2,55 @@ use pl0dash::{
     get_source::{open_source, Lexer},
     table::NameTable,
     codegen::CodeGenerator,
-    compile::Compiler,
+    compile::{Compiler, is_executable},
+    diagnostic::render_all,
 };
 
 fn main() {
-    let content = match open_source() {                       // ソースプログラムの内容を得る
-        Ok(content) => content,
+    let (filename, content) = match open_source() {           // ソースプログラムの内容を得る
+        Ok(source) => source,
         Err(err) => {
             println!("{}", err);
             return;
         }
     };
+    let flags: Vec<String> = std::env::args().skip(2).collect();
+    let print_code = flags.iter().any(|f| f == "-p");         // -p: 仮想機械のアセンブリを印字
+    let quiet = flags.iter().any(|f| f == "-q");              // -q: リスティングなどを出さず、プログラムの出力だけにする
+
     let mut lex = Lexer::new(&content);                       // 字句解析のための変数を設定
+    lex.set_file_name(&filename);
+    if !quiet {
+        println!("start compilation:\n");
+        lex.set_listing(Box::new(std::io::stdout()));         // 読んだ行をそのまま印字
+    }
     let mut table_ = NameTable::new();                        // 名前表を作成
     let mut gen = CodeGenerator::new(&mut table_);            // アセンブリ生成のための変数を設定
     let mut compiler = Compiler::new(&mut lex, &mut gen);     // ワンパスコンパイルのための変数を設定
-    if compiler.compile() {                                   // コンパイルして、
-        if let Some(flag) = std::env::args().nth(2) {         // 成功したとき
-            if flag.as_str() == "-p" {                        // -p フラグを渡されているときは
-                compiler.print_code();                        // 仮想機械のアセンブリを印字
-            } else {                                          // そうでなければ
-                compiler.execute();                           // アセンブリを仮想機械上で実行
-            }
-        } else {
-            compiler.execute();                               // フラグ指定がなければ実行
+    let result = compiler.compile();                          // コンパイルして、
+    if quiet {
+        render_all(&mut std::io::stderr(), compiler.diagnostics(), &content).unwrap();  // 診断を印字
+    } else {
+        render_all(&mut std::io::stdout(), compiler.diagnostics(), &content).unwrap();
+    }
+    let diagnostics = match result {
+        Ok(diagnostics) => diagnostics,
+        Err(_) => {                                           // 続けられないエラーのときは中断
+            eprintln!("abort compilation");
+            std::process::exit(1);
+        }
+    };
+    if is_executable(&diagnostics) {                          // 成功したとき
+        if print_code {
+            compiler.print_code();                            // 仮想機械のアセンブリを印字
+            return;
+        }
+        if !quiet {
+            println!("\nstart execution:");
+        }
+        if let Err(err) = compiler.execute() {                // アセンブリを仮想機械上で実行
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
     }
 }
@@ -1,8 +1,13 @@
 use std::collections::HashMap;
+use std::fmt;
+
+use super::error::CompileError;
+use super::get_source::Span;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum KindT {                      // Identifierの種類
-    VarId, FuncId, ParId, ConstId,
+    VarId, FuncId, ParId, ConstId, ProcId,
+    ArrayId, ArrayParId, VarParId,
 }
 
 // impl KindT {
@@ -12,25 +17,50 @@ pub enum KindT {                      // Identifierの種類
 //             KindT::FuncId => "func",
 //             KindT::ParId => "par",
 //             KindT::ConstId => "const",
+//             KindT::ProcId => "proc",
+//             KindT::ArrayId => "array",
+//             KindT::ArrayParId => "array par",
+//             KindT::VarParId => "var par",
 //         }
 //     }
 // }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Type {                       // 値の型
+    Integer, Boolean,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Type::Integer => "integer",
+            Type::Boolean => "boolean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParMode {                    // パラメータの渡し方
+    Value,                            // 値を渡す
+    Var,                              // 変数の番地を渡す（varパラメータ）
+    Array(i32),                       // 要素数が同じ配列の先頭番地を渡す
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct RelAddr {                  // 変数、パラメータ、関数のアドレスの型
     pub level: i32,
     pub addr: i32,
 }
 
-const MAXTABLE: usize = 100;          // 名前表の最大長さ
 // const MAXNAME: usize = 31;            // 名前の最大長さ
-const MAXLEVEL: usize = 5;            // ブロックの最大深さ
 
 #[derive(Copy, Clone, Debug)]
 pub enum TableEntryU {                // unionに相当する型
     Value(i32),                          // 定数の場合：値
-    Func { raddr: RelAddr, pars: i32 },  // 関数の場合：先頭アドレス、パラメータ数
+    Func { raddr: RelAddr, pars: i32 },  // 関数・手続きの場合：先頭アドレス、パラメータ数
     RelAddr(RelAddr),                    // 変数・パラメータの場合：アドレス
+    Array { raddr: RelAddr, size: i32 }, // 配列の場合：先頭アドレス（パラメータなら先頭番地の入る場所）、要素数
 }
 
 #[derive(Clone, Debug)]
@@ -38,6 +68,8 @@ pub struct TableEntry {               // 名前表のエントリの型
     kind: KindT,                      // 名前の種類
     name: String,                     // 名前の綴り
     u: TableEntryU,                   // unionに相当する型
+    ty: Type,                         // 変数・パラメータ・配列の要素の型、関数の値の型
+    span: Span,                       // 宣言された位置
 }
 
 #[derive(Clone, Debug)]
@@ -45,10 +77,20 @@ pub struct NameTable {
     table: HashMap<i32, TableEntry>,  // 名前表
     t_index: i32,                     // 名前表のインデックス
     level: i32,                       // 現在のブロックレベル
-    index: [i32; MAXLEVEL],           // index[i]にはブロックレベルiの最後のインデックス
-    addr: [i32; MAXLEVEL],            // addr[i]にはブロックレベルiの最後の変数の番地
+    index: Vec<i32>,                  // index[i]にはブロックレベルiの最後のインデックス
+    addr: Vec<i32>,                   // addr[i]にはブロックレベルiの最後の変数の番地
     local_addr: i32,                  // 現在のブロックの最後の変数の番地
     tf_index: i32,                    // 名前表の関数名のインデックス
+    max_names: Option<usize>,         // 名前表の最大長さ（Noneなら制限なし）
+    max_level: Option<usize>,         // ブロックの最大深さ（Noneなら制限なし）
+    par_modes: HashMap<i32, Vec<(ParMode, Type)>>,  // 関数・手続きの各パラメータの渡し方と型
+    forwards: HashMap<i32, Vec<usize>>,  // 定義がまだの前方宣言と、それを呼ぶcal命令（定義の先頭番地が決まったらパッチ）
+}
+
+impl Default for NameTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NameTable {
@@ -57,33 +99,55 @@ impl NameTable {
             table: HashMap::new(),
             t_index: 0,
             level: -1,
-            index: [0; MAXLEVEL],
-            addr: [0; MAXLEVEL],
+            index: Vec::new(),
+            addr: Vec::new(),
             local_addr: 0,
             tf_index: 0,
+            max_names: None,
+            max_level: None,
+            par_modes: HashMap::new(),
+            forwards: HashMap::new(),
+        }
+    }
+    pub fn set_max_names(&mut self, max_names: Option<usize>) {  // 名前表の最大長さをセット
+        self.max_names = max_names;
+    }
+    pub fn set_max_level(&mut self, max_level: Option<usize>) {  // ブロックの最大深さをセット
+        self.max_level = max_level;
+    }
+    fn check_names(&self) -> Result<(), CompileError> {     // 名前表にもう一つ名前を登録できるかのチェック
+        match self.max_names {
+            Some(limit) if self.t_index as usize >= limit => Err(CompileError::TooManyNames { limit }),
+            _ => Ok(()),
         }
     }
-    pub fn block_begin(&mut self, first_addr: i32) {        // ブロックの始まり（最初の変数の番地）で呼ばれる
+    pub fn block_begin(&mut self, first_addr: i32) -> Result<(), CompileError> { // ブロックの始まり（最初の変数の番地）で呼ばれる
         if self.level == -1 {                    // 主ブロックの時、初期設定
             self.local_addr = first_addr;
             self.t_index = 0;
             self.level += 1;
-            return;
+            return Ok(());
         }
-        if self.level == (MAXLEVEL - 1) as i32 {
-            eprintln!("too many nested blocks");
-            std::process::exit(1);
+        if let Some(limit) = self.max_level {
+            if self.level as usize + 1 >= limit {
+                return Err(CompileError::TooManyNestedBlocks { limit });
+            }
         }
-        self.index[self.level as usize] = self.t_index;     // 今までのブロックの情報を格納
-        self.addr[self.level as usize] = self.local_addr;
+        self.index.push(self.t_index);                      // 今までのブロックの情報を格納
+        self.addr.push(self.local_addr);
         self.local_addr = first_addr;                       // 新しいブロックの最初の変数の番地
         self.level += 1;                                    // 新しいブロックのレベル
+        Ok(())
     }
     pub fn block_end(&mut self) {                           // ブロックの終わりで呼ばれる
         self.level -= 1;
         if self.level > -1 {
-            self.t_index = self.index[self.level as usize];  // 一つ外側のブロックの情報を回復
-            self.local_addr = self.addr[self.level as usize];
+            self.t_index = self.index.pop().unwrap();       // 一つ外側のブロックの情報を回復
+            self.local_addr = self.addr.pop().unwrap();
+            let t_index = self.t_index;
+            self.table.retain(|&i, _| i <= t_index);        // 閉じたブロックの名前は見えなくする
+            self.par_modes.retain(|&i, _| i <= t_index);
+            self.forwards.retain(|&i, _| i <= t_index);
         }
         // self.t_index = self.index[self.level as usize];
         // self.local_addr = self.addr[self.level as usize];
@@ -94,7 +158,7 @@ impl NameTable {
     pub fn func_pars(&self) -> Option<i32> {                // 現ブロックの関数のパラメータ数を返す
         if self.level > 0 {
             self.table.get(&self.index[(self.level - 1) as usize])
-                .map_or(None, |entry| {
+                .and_then(|entry| {
                     match entry.u {
                         TableEntryU::Func {raddr: _, pars: p} => Some(p),
                         _ => None
@@ -111,78 +175,113 @@ impl NameTable {
         //         }
         //     })
     }
-    pub fn enter_table_func(&mut self, id: String, v: i32) -> i32 {  // 名前表に関数名と先頭番地を登録
-        self.t_index += 1;
-        if self.t_index < MAXTABLE as i32 {
-            self.table.insert(self.t_index, TableEntry {
-                kind: KindT::FuncId,
-                name: id,
-                u: TableEntryU::Func {
-                    raddr: RelAddr { level: self.level, addr: v },   // addr: 関数の先頭番地
-                    pars: 0,                                         // pars: パラメータ数の初期値
-                }
-            });
-            self.tf_index = self.t_index;
-            self.t_index
-        } else {
-            eprintln!("too many names");
-            std::process::exit(1);
-        }
+    pub fn returns_value(&self) -> bool {                   // 現ブロックが値を返す関数のブロックかどうか
+        self.level > 0
+            && self.table.get(&self.index[(self.level - 1) as usize])
+                .is_some_and(|entry| entry.kind == KindT::FuncId)
     }
-    pub fn enter_table_par(&mut self, id: String) -> i32 {  // 名前表にパラメータ名を登録
+    pub fn enter_table_func(&mut self, id: String, v: i32, span: Span) -> Result<i32, CompileError> { // 名前表に関数名と先頭番地を登録
+        self.enter_table_subprogram(KindT::FuncId, id, v, span)
+    }
+    pub fn enter_table_proc(&mut self, id: String, v: i32, span: Span) -> Result<i32, CompileError> { // 名前表に手続き名と先頭番地を登録
+        self.enter_table_subprogram(KindT::ProcId, id, v, span)
+    }
+    fn enter_table_subprogram(&mut self, kind: KindT, id: String, v: i32, span: Span) -> Result<i32, CompileError> {
+        self.check_names()?;
         self.t_index += 1;
-        if self.t_index < MAXTABLE as i32 {
-            self.table.insert(self.t_index, TableEntry {
-                kind: KindT::ParId,
-                name: id,
-                u: TableEntryU::RelAddr(RelAddr { level: self.level, addr: 0 })
-            });
-            let entry = self.table.get(&self.tf_index).unwrap().clone();
-            match entry.u {
-                TableEntryU::Func { raddr: r, pars: p } => {
-                    self.table.insert(self.tf_index, TableEntry {
-                        u: TableEntryU::Func { raddr: r, pars: p + 1 },  // 関数のパラメータ数のカウント
-                        ..entry
-                    })
-                },
-                _ => unreachable!(),
-            };
-            self.t_index
-        } else {
-            eprintln!("too many names");
-            std::process::exit(1);
-        }
+        self.table.insert(self.t_index, TableEntry {
+            kind,
+            name: id,
+            u: TableEntryU::Func {
+                raddr: RelAddr { level: self.level, addr: v },   // addr: 関数の先頭番地
+                pars: 0,                                         // pars: パラメータ数の初期値
+            },
+            ty: Type::Integer,                                   // 関数の値の型はあとでset_typeする
+            span,
+        });
+        self.tf_index = self.t_index;
+        self.par_modes.insert(self.t_index, Vec::new());
+        Ok(self.t_index)
+    }
+    pub fn enter_table_par(&mut self, id: String, ty: Type, span: Span) -> Result<i32, CompileError> { // 名前表にパラメータ名と型を登録
+        let u = TableEntryU::RelAddr(RelAddr { level: self.level, addr: 0 });
+        self.enter_par(KindT::ParId, id, u, ParMode::Value, ty, span)
     }
-    pub fn enter_table_var(&mut self, id: String) -> i32 {  // 名前表に変数名を登録
+    pub fn enter_table_var_par(&mut self, id: String, ty: Type, span: Span) -> Result<i32, CompileError> { // 名前表にvarパラメータ名と型を登録
+        let u = TableEntryU::RelAddr(RelAddr { level: self.level, addr: 0 });
+        self.enter_par(KindT::VarParId, id, u, ParMode::Var, ty, span)
+    }
+    pub fn enter_table_array_par(&mut self, id: String, size: i32, ty: Type, span: Span) -> Result<i32, CompileError> { // 名前表に配列のパラメータ名と要素数、要素の型を登録
+        let u = TableEntryU::Array { raddr: RelAddr { level: self.level, addr: 0 }, size };
+        self.enter_par(KindT::ArrayParId, id, u, ParMode::Array(size), ty, span)
+    }
+    fn enter_par(&mut self, kind: KindT, id: String, u: TableEntryU, mode: ParMode, ty: Type, span: Span)
+                 -> Result<i32, CompileError> {
+        self.check_names()?;
         self.t_index += 1;
-        if self.t_index < MAXTABLE as i32 {
-            self.table.insert(self.t_index, TableEntry {
-                kind: KindT::VarId,
-                name: id,
-                u: TableEntryU::RelAddr(RelAddr {
-                    level: self.level, addr: self.local_addr,
+        self.table.insert(self.t_index, TableEntry { kind, name: id, u, ty, span });
+        self.par_modes.entry(self.tf_index).or_default().push((mode, ty));
+        let entry = self.table.get(&self.tf_index).unwrap().clone();
+        match entry.u {
+            TableEntryU::Func { raddr: r, pars: p } => {
+                self.table.insert(self.tf_index, TableEntry {
+                    u: TableEntryU::Func { raddr: r, pars: p + 1 },  // 関数のパラメータ数のカウント
+                    ..entry
                 })
-            });
-            self.local_addr += 1;
-            self.t_index
-        } else {
-            eprintln!("too many names");
-            std::process::exit(1);
-        }
+            },
+            _ => unreachable!(),
+        };
+        Ok(self.t_index)
     }
-    pub fn enter_table_const(&mut self, id: String, v: i32) -> i32 {  // 名前表に定数名とその値を登録
+    pub fn enter_table_var(&mut self, id: String, ty: Type, span: Span) -> Result<i32, CompileError> { // 名前表に変数名と型を登録
+        self.check_names()?;
         self.t_index += 1;
-        if self.t_index < MAXTABLE as i32 {
-            self.table.insert(self.t_index, TableEntry {
-                kind: KindT::ConstId,
-                name: id,
-                u: TableEntryU::Value(v)
-            });
-            self.t_index
-        } else {
-            eprintln!("too many names");
-            std::process::exit(1);
-        }
+        self.table.insert(self.t_index, TableEntry {
+            kind: KindT::VarId,
+            name: id,
+            u: TableEntryU::RelAddr(RelAddr {
+                level: self.level, addr: self.local_addr,
+            }),
+            ty,
+            span,
+        });
+        self.alloc(1)?;
+        Ok(self.t_index)
+    }
+    fn alloc(&mut self, size: i32) -> Result<(), CompileError> {  // 現在のブロックにsize個分の番地をとる
+        self.local_addr = self.local_addr.checked_add(size).ok_or(CompileError::FrameTooLarge)?;
+        Ok(())
+    }
+    pub fn enter_table_temp(&mut self, span: Span) -> Result<i32, CompileError> { // 名前のない作業用の変数を登録、番地はtableが決める
+        self.enter_table_var(String::new(), Type::Integer, span)  // 空の名前は検索されない
+    }
+    pub fn enter_table_array(&mut self, id: String, size: i32, ty: Type, span: Span) -> Result<i32, CompileError> { // 名前表に配列名と要素の型を登録、要素数だけ番地をとる
+        self.check_names()?;
+        self.t_index += 1;
+        self.table.insert(self.t_index, TableEntry {
+            kind: KindT::ArrayId,
+            name: id,
+            u: TableEntryU::Array {
+                raddr: RelAddr { level: self.level, addr: self.local_addr },
+                size,
+            },
+            ty,
+            span,
+        });
+        self.alloc(size)?;
+        Ok(self.t_index)
+    }
+    pub fn enter_table_const(&mut self, id: String, v: i32, span: Span) -> Result<i32, CompileError> { // 名前表に定数名とその値を登録
+        self.check_names()?;
+        self.t_index += 1;
+        self.table.insert(self.t_index, TableEntry {
+            kind: KindT::ConstId,
+            name: id,
+            u: TableEntryU::Value(v),
+            ty: Type::Integer,                // 定数は整数だけ
+            span,
+        });
+        Ok(self.t_index)
     }
     pub fn end_par(&mut self) {                             // パラメータ宣言部の最後で呼ばれる
         let pars = match self.table.get(&self.tf_index).unwrap().u {
@@ -192,17 +291,13 @@ impl NameTable {
         if pars == 0 { return; }
         for i in 1..=(pars as usize) {                      // 各パラメータの番地を決める
             let entry = self.table.get(&(self.tf_index+(i as i32))).unwrap().clone();
-            match entry.u {
-                TableEntryU::RelAddr(r) => {
-                    self.table.insert(self.tf_index+(i as i32), TableEntry {
-                        u: TableEntryU::RelAddr(RelAddr {
-                            level: r.level, addr: (i as i32) - 1 - pars,
-                        }),
-                        ..entry
-                    });
-                },
+            let addr = (i as i32) - 1 - pars;
+            let u = match entry.u {
+                TableEntryU::RelAddr(r) => TableEntryU::RelAddr(RelAddr { addr, ..r }),
+                TableEntryU::Array { raddr: r, size } => TableEntryU::Array { raddr: RelAddr { addr, ..r }, size },
                 _ => unreachable!(),
-            }
+            };
+            self.table.insert(self.tf_index+(i as i32), TableEntry { u, ..entry });
         }
     }
     pub fn change_v(&mut self, ti: i32, new_val: i32) {  // 名前表.get(&ti)の値（関数の先頭番地）の変更
@@ -222,22 +317,34 @@ impl NameTable {
             }
         } else {                                  // ti=0の時、つまり主ブロックのエントリを作成していないので、新しいエントリを作成
             self.table.insert(ti, TableEntry {
-                kind: KindT::FuncId,
+                kind: KindT::ProcId,              // 主ブロックは値を返さない
                 name: String::from(""),
                 u: TableEntryU::Func { raddr: RelAddr {
                     addr: new_val, level: 0,
                 }, pars: 0 },
+                ty: Type::Integer,
+                span: Span::default(),
             });
         }
     }
-    pub fn search_t(&mut self, id: String, k: KindT) -> i32 {  // 名前idの名前表の位置を返す
-        for (index, entry) in self.table.iter() {
-            if entry.name == id { return *index; }                 // 名前があった
+    pub fn search_t(&self, id: &str) -> Option<i32> {       // 名前idの名前表の位置を返す（見つからなければNone）
+        (1..=self.t_index).rev()                            // 内側のブロックから外側へ向かって探す
+            .find(|index| self.table.get(index).is_some_and(|entry| entry.name == id))
+    }
+    pub fn similar_name(&self, id: &str) -> Option<String> {  // 見えている名前の中からidに綴りの近いものを返す
+        let len = id.chars().count();                           // 全部を書き換えるような候補（1文字の名前どうし）は出さない
+        let limit = std::cmp::max(1, len / 3);                  // 許容する編集距離
+        let mut best: Option<(usize, &str)> = None;
+        for index in (1..=self.t_index).rev() {
+            if let Some(entry) = self.table.get(&index) {
+                if entry.name.is_empty() { continue; }
+                let d = edit_distance(id, &entry.name);
+                if d <= limit && d < len && best.is_none_or(|(bd, _)| d < bd) {
+                    best = Some((d, &entry.name));
+                }
+            }
         }
-        // Lexerのerrorメソッドを使うのにミュータブルな参照が必要なので、errorを吐けない
-        // error出力のために別のモジュールを設けるべきなのかもしれない
-        if k == KindT::VarId { return self.enter_table_var(id); }  // 名前がなかったら、変数の時は仮登録
-        0
+        best.map(|(_, name)| name.to_string())
     }
     pub fn kind_t(&self, i: i32) -> KindT {                 // 名前表.get(&i)の種類を返す
         self.table.get(&i).unwrap().kind
@@ -248,6 +355,7 @@ impl NameTable {
             TableEntryU::RelAddr(r) => r,
             TableEntryU::Func { raddr: r, pars: _ } => r,   // nameTable[ti].u.raddrで共用体のメンバにアクセスする時、
                                                             // nameTable[ti].f.u.raddrにもアクセスできていると考えた
+            TableEntryU::Array { raddr: r, size: _ } => r,
             _ => unreachable!(),
         }
     }
@@ -265,7 +373,112 @@ impl NameTable {
             _ => unreachable!(),
         }
     }
+    pub fn array_size(&self, ti: i32) -> i32 {              // 名前表.get(&ti)の配列の要素数を返す
+        let entry = self.table.get(&ti).unwrap();
+        match entry.u {
+            TableEntryU::Array { raddr: _, size } => size,
+            _ => unreachable!(),
+        }
+    }
+    pub fn par_mode(&self, ti: i32, n: usize) -> Option<(ParMode, Type)> {  // 関数・手続きtiのn番目（0から）のパラメータの渡し方と型を返す
+        self.par_modes.get(&ti).and_then(|modes| modes.get(n).copied())
+    }
+    pub fn type_t(&self, ti: i32) -> Type {                 // 名前表.get(&ti)の型を返す
+        self.table.get(&ti).unwrap().ty
+    }
+    pub fn set_type(&mut self, ti: i32, ty: Type) {         // 名前表.get(&ti)の型（関数の値の型）の変更
+        if let Some(entry) = self.table.get_mut(&ti) {
+            entry.ty = ty;
+        }
+    }
+    pub fn return_type(&self) -> Type {                     // 現ブロックの関数の値の型を返す
+        self.table.get(&self.index[(self.level - 1).max(0) as usize])
+            .map_or(Type::Integer, |entry| entry.ty)
+    }
+    pub fn name(&self, ti: i32) -> &str {                   // 名前表.get(&ti)の名前の綴りを返す
+        &self.table.get(&ti).unwrap().name
+    }
+    pub fn enter_forward(&mut self, ti: i32) {              // 関数・手続きtiを前方宣言として登録
+        self.forwards.insert(ti, Vec::new());
+    }
+    pub fn search_forward(&self, id: &str, kind: KindT) -> Option<i32> {  // 現ブロックで前方宣言された、まだ定義のない関数・手続きidを探す
+        self.search_t(id).filter(|ti| {
+            self.forwards.contains_key(ti) && self.kind_t(*ti) == kind && self.rel_addr(*ti).level == self.level
+        })
+    }
+    pub fn add_forward_call(&mut self, ti: i32, i: usize) {  // tiが前方宣言ならi番地のcal命令をあとでパッチする
+        if let Some(calls) = self.forwards.get_mut(&ti) {
+            calls.push(i);
+        }
+    }
+    pub fn resolve_forward(&mut self, ti: i32) -> Vec<usize> {  // 前方宣言tiの定義が見つかった。パッチするcal命令の番地を返す
+        self.forwards.remove(&ti).unwrap_or_default()
+    }
+    pub fn unresolved_forwards(&mut self) -> Vec<i32> {     // 現ブロックで前方宣言され、定義のなかった関数・手続きを取り除いて返す
+        let mut unresolved: Vec<i32> = self.forwards.keys().copied()
+            .filter(|ti| self.rel_addr(*ti).level == self.level)
+            .collect();
+        unresolved.sort();
+        for ti in &unresolved {
+            self.forwards.remove(ti);
+        }
+        unresolved
+    }
+    pub fn same_signature(&self, a: i32, b: i32) -> bool {  // 2つの関数・手続きのパラメータと値の型が同じか
+        self.pars(a) == self.pars(b) && self.par_modes.get(&a) == self.par_modes.get(&b) && self.type_t(a) == self.type_t(b)
+    }
+    pub fn decl_span(&self, ti: i32) -> Span {              // 名前表.get(&ti)の宣言された位置を返す
+        self.table.get(&ti).unwrap().span
+    }
     pub fn frame_l(&self) -> i32 {     // そのブロックで実行時に必要とするメモリ容量
         self.local_addr
     }
 }
+
+fn edit_distance(a: &str, b: &str) -> usize {  // 2つの名前の編集距離（隣接文字の入れ替えも1と数える）
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() { row[0] = i; }
+    for (j, cell) in d[0].iter_mut().enumerate() { *cell = j; }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i-1] == b[j-1] { 0 } else { 1 };
+            d[i][j] = (d[i-1][j-1] + cost).min(d[i-1][j] + 1).min(d[i][j-1] + 1);
+            if i > 1 && j > 1 && a[i-1] == b[j-2] && a[i-2] == b[j-1] {
+                d[i][j] = d[i][j].min(d[i-2][j-2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inner_names_shadow_outer_names() {
+        let mut table = NameTable::new();
+        table.block_begin(2).unwrap();
+        let outer = table.enter_table_var(String::from("x"), Type::Integer, Span::default()).unwrap();
+        table.block_begin(2).unwrap();
+        let inner = table.enter_table_var(String::from("x"), Type::Integer, Span::default()).unwrap();
+        table.enter_table_var(String::from("local"), Type::Integer, Span::default()).unwrap();
+        assert_eq!(table.search_t("x"), Some(inner));
+        table.block_end();
+        assert_eq!(table.search_t("x"), Some(outer));
+        assert_eq!(table.search_t("local"), None);        // 閉じたブロックの名前は見えない
+    }
+
+    #[test]
+    fn similar_name_suggests_visible_names() {
+        let mut table = NameTable::new();
+        table.block_begin(2).unwrap();
+        table.enter_table_var(String::from("count"), Type::Integer, Span::default()).unwrap();
+        table.enter_table_var(String::from("total"), Type::Integer, Span::default()).unwrap();
+        assert_eq!(table.similar_name("cuont"), Some(String::from("count")));
+        assert_eq!(table.similar_name("totl"), Some(String::from("total")));
+        assert_eq!(table.similar_name("zzz"), None);
+    }
+}
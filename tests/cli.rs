@@ -0,0 +1,36 @@
+use std::process::Command;
+
+fn pl0dash(args: &[&str]) -> (String, String) {   // コンパイラを起動して、標準出力と標準エラー出力を返す
+    let output = Command::new(env!("CARGO_BIN_EXE_pl0dash"))
+        .args(args)
+        .output()
+        .expect("failed to run pl0dash");
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+}
+
+#[test]
+fn quiet_mode_prints_only_program_output() {
+    let (stdout, stderr) = pl0dash(&["test_src/fizzbuzz.pl0d", "-q"]);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(&lines[..5], &["1", "2", "Fizz", "4", "Buzz"]);
+    assert!(!stdout.contains("start"));
+    assert!(stderr.is_empty());
+}
+
+#[test]
+fn quiet_mode_sends_diagnostics_to_stderr() {
+    let path = std::env::temp_dir().join("pl0dash_quiet_error.pl0d");
+    std::fs::write(&path, "var count;\nbegin cuont := 1 end.\n").unwrap();
+    let (stdout, stderr) = pl0dash(&[path.to_str().unwrap(), "-q"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(stdout.is_empty());
+    assert!(stderr.contains("error[E007]: undeclared identifier: cuont (did you mean count?)"));
+}
+
+#[test]
+fn listing_is_printed_by_default() {
+    let (stdout, _) = pl0dash(&["test_src/fizzbuzz.pl0d"]);
+    let source = std::fs::read_to_string("test_src/fizzbuzz.pl0d").unwrap();
+    assert!(stdout.starts_with("start compilation:\n"));
+    assert!(stdout.contains(source.lines().next().unwrap()));
+}